@@ -0,0 +1,755 @@
+/*
+Copyright (c) 2024-2025 Mark Hughes
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use bytes::Bytes;
+use color_eyre::eyre::{Report, Result};
+use futures::future::try_join_all;
+use http::StatusCode;
+use lru::LruCache;
+use xor_name::XorName;
+
+use autonomi::chunk::DataMapChunk;
+use autonomi::client::data::DataAddress;
+use autonomi::files::PublicArchive;
+
+use dweb::client::DwebClient;
+use dweb::files::directory::{get_content, Tree};
+use dweb::history::{History, HistoryAddress};
+
+use crate::awe_errors::NotCached;
+use crate::awe_stream::{DataStream, STREAMING_THRESHOLD};
+
+const CACHE_DIRECTORY: &str = "awe/cache";
+const HISTORY_DIRECTORY: &str = "awe/history";
+const BYTES_PER_MEGABYTE: u64 = 1024 * 1024;
+
+/// A bounded on-disk cache of content fetched from Autonomi
+///
+/// Content on Autonomi is addressed by its hash and so never changes. This makes it
+/// safe to keep anything we fetch for as long as we like. Each item is stored in
+/// a file named by its key, and the least recently used items are removed when the
+/// total size exceeds the limit.
+pub struct ContentCache {
+    directory: PathBuf,
+    max_bytes: u64,
+    index: Mutex<CacheIndex>,
+}
+
+struct CacheIndex {
+    /// Size in bytes of each cached item, in order of use
+    entries: LruCache<String, u64>,
+    total_bytes: u64,
+}
+
+impl ContentCache {
+    /// Open the cache in the platform data directory using the size set on the CLI
+    ///
+    /// A size of zero disables the cache.
+    pub fn open_default() -> Option<ContentCache> {
+        use crate::cli_options::Opt;
+        use clap::Parser;
+        let opt = Opt::parse();
+
+        if opt.cache_size == 0 {
+            println!("DEBUG content cache disabled");
+            return None;
+        }
+
+        let Some(data_dir) = dirs_next::data_dir() else {
+            println!("Content cache disabled: unable to find the platform data directory");
+            return None;
+        };
+
+        match ContentCache::open(data_dir.join(CACHE_DIRECTORY), opt.cache_size) {
+            Ok(cache) => Some(cache),
+            Err(e) => {
+                println!("Content cache disabled: {e}");
+                None
+            }
+        }
+    }
+
+    /// Open (or create) a cache in directory, limited to max_megabytes
+    pub fn open(directory: PathBuf, max_megabytes: u64) -> Result<ContentCache> {
+        fs::create_dir_all(&directory)?;
+
+        // Rebuild the index, oldest first, so recency survives a restart
+        let mut existing = Vec::<(SystemTime, String, u64)>::new();
+        for entry in fs::read_dir(&directory)? {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            if !metadata.is_file() {
+                continue;
+            }
+            if let Some(key) = entry.file_name().to_str() {
+                if key.ends_with(".tmp") {
+                    let _ = fs::remove_file(entry.path());
+                    continue;
+                }
+                let used = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+                existing.push((used, key.to_string(), metadata.len()));
+            }
+        }
+        existing.sort();
+
+        let mut index = CacheIndex {
+            entries: LruCache::unbounded(),
+            total_bytes: 0,
+        };
+        for (_, key, size) in existing {
+            index.total_bytes += size;
+            index.entries.put(key, size);
+        }
+
+        println!(
+            "DEBUG content cache at {:?} holds {} items ({} bytes)",
+            directory,
+            index.entries.len(),
+            index.total_bytes
+        );
+
+        let cache = ContentCache {
+            directory,
+            max_bytes: max_megabytes * BYTES_PER_MEGABYTE,
+            index: Mutex::new(index),
+        };
+        let evicted = cache.evict(&mut cache.index.lock().unwrap());
+        remove_evicted(&cache.directory, &evicted);
+        Ok(cache)
+    }
+
+    /// Return the cache key for content given by a DataMapChunk or DataAddress
+    pub fn key_for(
+        datamap_chunk: &Option<DataMapChunk>,
+        data_address: &Option<DataAddress>,
+    ) -> Option<String> {
+        if let Some(datamap_chunk) = datamap_chunk {
+            // A datamap can be large, so use its hash
+            let hash = XorName::from_content(datamap_chunk.to_hex().as_bytes());
+            Some(format!("m{hash:x}"))
+        } else if let Some(data_address) = data_address {
+            Some(format!("d{}", data_address.to_hex()))
+        } else {
+            None
+        }
+    }
+
+    /// Return the cached content for key, if present
+    ///
+    /// The index is only locked while it is updated, and files are read and written
+    /// on a blocking thread so that other requests are not held up by the disk.
+    pub async fn get(&self, key: &str) -> Option<Bytes> {
+        self.index.lock().unwrap().entries.get(key)?;
+
+        let path = self.directory.join(key);
+        let read = tokio::task::spawn_blocking(move || {
+            let content = fs::read(&path)?;
+            // Record the use so that recency survives a restart
+            if let Ok(file) = fs::File::options().write(true).open(&path) {
+                let _ = file.set_modified(SystemTime::now());
+            }
+            Ok::<Vec<u8>, std::io::Error>(content)
+        })
+        .await;
+
+        match read {
+            Ok(Ok(content)) => Some(Bytes::from(content)),
+            _ => {
+                // Removed behind our back, so forget it
+                let mut index = self.index.lock().unwrap();
+                if let Some(size) = index.entries.pop(key) {
+                    index.total_bytes -= size;
+                }
+                None
+            }
+        }
+    }
+
+    /// Store content under key, evicting least recently used items if over the limit
+    pub async fn put(&self, key: &str, content: Bytes) {
+        let size = content.len() as u64;
+        if size > self.max_bytes {
+            return;
+        }
+
+        // Claim the key before writing so the same content is not written twice at once
+        let evicted = {
+            let mut index = self.index.lock().unwrap();
+            if index.entries.contains(key) {
+                return;
+            }
+            index.total_bytes += size;
+            index.entries.put(key.to_string(), size);
+            self.evict(&mut index)
+        };
+
+        // Write then rename, so a partial file is never seen as cached content
+        let directory = self.directory.clone();
+        let file_key = key.to_string();
+        let written = tokio::task::spawn_blocking(move || {
+            remove_evicted(&directory, &evicted);
+            let path = directory.join(&file_key);
+            let temp_path = directory.join(format!("{file_key}.tmp"));
+            let written =
+                fs::write(&temp_path, &content).and_then(|_| fs::rename(&temp_path, &path));
+            if written.is_err() {
+                let _ = fs::remove_file(&temp_path);
+            }
+            written
+        })
+        .await;
+
+        let error = match written {
+            Ok(Ok(())) => return,
+            Ok(Err(e)) => e.to_string(),
+            Err(e) => e.to_string(),
+        };
+        println!("DEBUG failed to write {key} to content cache: {error}");
+        let mut index = self.index.lock().unwrap();
+        if let Some(size) = index.entries.pop(key) {
+            index.total_bytes -= size;
+        }
+    }
+
+    /// Remove least recently used items from the index until within the limit
+    ///
+    /// Returns the keys removed, whose files are for the caller to delete.
+    fn evict(&self, index: &mut CacheIndex) -> Vec<String> {
+        let mut evicted = Vec::new();
+        while index.total_bytes > self.max_bytes {
+            let Some((key, size)) = index.entries.pop_lru() else {
+                break;
+            };
+            index.total_bytes -= size;
+            println!("DEBUG content cache evicted {key} ({size} bytes)");
+            evicted.push(key);
+        }
+        evicted
+    }
+}
+
+fn remove_evicted(directory: &Path, evicted: &[String]) {
+    for key in evicted {
+        let _ = fs::remove_file(directory.join(key));
+    }
+}
+
+/// Get content from the cache if present, or fetch from the network and cache it
+///
+/// With no client (when offline) content which is not cached is a NotCached error.
+pub async fn get_content_cached(
+    client: Option<&DwebClient>,
+    cache: Option<&ContentCache>,
+    datamap_chunk: Option<DataMapChunk>,
+    data_address: Option<DataAddress>,
+) -> Result<Bytes> {
+    let key = ContentCache::key_for(&datamap_chunk, &data_address);
+    if let (Some(cache), Some(key)) = (cache, &key) {
+        if let Some(content) = cache.get(key).await {
+            println!("DEBUG content cache hit for {key}");
+            return Ok(content);
+        }
+    }
+
+    let Some(client) = client else {
+        return Err(not_cached(&key));
+    };
+    let content = get_content(client, datamap_chunk, data_address).await?;
+
+    if let (Some(cache), Some(key)) = (cache, &key) {
+        cache.put(key, content.clone()).await;
+    }
+    Ok(content)
+}
+
+/// Content small enough to hold in memory, or a stream for a larger file
+pub enum FetchedContent {
+    Whole(Bytes),
+    Stream(DataStream),
+}
+
+/// Like get_content_cached() except that a file larger than STREAMING_THRESHOLD is
+/// returned as a DataStream, without fetching its content or adding it to the cache
+pub async fn get_content_or_stream_cached(
+    client: Option<&DwebClient>,
+    cache: Option<&ContentCache>,
+    datamap_chunk: Option<DataMapChunk>,
+    data_address: Option<DataAddress>,
+) -> Result<FetchedContent> {
+    let key = ContentCache::key_for(&datamap_chunk, &data_address);
+    if let (Some(cache), Some(key)) = (cache, &key) {
+        if let Some(content) = cache.get(key).await {
+            println!("DEBUG content cache hit for {key}");
+            return Ok(FetchedContent::Whole(content));
+        }
+    }
+
+    let Some(client) = client else {
+        return Err(not_cached(&key));
+    };
+    let stream = DataStream::open(client, datamap_chunk, data_address).await?;
+    if stream.size() > STREAMING_THRESHOLD {
+        println!("DEBUG streaming {} bytes", stream.size());
+        return Ok(FetchedContent::Stream(stream));
+    }
+
+    // Read through the stream so that the DataMap is not fetched again
+    let content = stream.read_all().await?;
+    if let (Some(cache), Some(key)) = (cache, &key) {
+        cache.put(key, content.clone()).await;
+    }
+    Ok(FetchedContent::Whole(content))
+}
+
+fn not_cached(key: &Option<String>) -> Report {
+    let key = key.clone().unwrap_or(String::from("Content"));
+    Report::new(NotCached(key))
+}
+
+/// The hex address of a History and the version asked for
+type ResolvingKey = (String, Option<u32>);
+
+/// A cache of the Tree for each version of a History, and of directory Trees
+///
+/// A published version never changes so its Tree is kept for the life of the app. The
+/// version that is "latest" can change, so that is remembered only for a short time
+/// before the History is read again from the network.
+///
+/// Trees and the latest version of each History are also saved on disk so that
+/// sites visited before can be browsed offline, even after a restart.
+pub struct HistoryCache {
+    latest_ttl: Duration,
+    directory: Option<PathBuf>,
+    trees: Mutex<HashMap<(String, u32), Arc<Tree>>>,
+    /// When the latest version of each History was resolved, and its version
+    latest: Mutex<HashMap<String, (SystemTime, u32)>>,
+    /// When each version of a History was published, from the timestamp of its entry
+    published: Mutex<HashMap<(String, u32), u64>>,
+    /// Trees given by the address of their archive (awm://)
+    archives: Mutex<HashMap<String, Arc<Tree>>>,
+    /// A lock for each History and version being resolved from the network, held so that
+    /// concurrent requests for one page resolve it once without waiting on other sites
+    resolving: Mutex<HashMap<ResolvingKey, Arc<tauri::async_runtime::Mutex<()>>>>,
+}
+
+impl HistoryCache {
+    /// Create a cache using the time to live for the latest version set on the CLI,
+    /// saved in the platform data directory unless the content cache is disabled
+    pub fn new_default() -> HistoryCache {
+        use crate::cli_options::Opt;
+        use clap::Parser;
+        let opt = Opt::parse();
+
+        let directory = match dirs_next::data_dir() {
+            Some(data_dir) if opt.cache_size > 0 => {
+                let directory = data_dir.join(HISTORY_DIRECTORY);
+                match fs::create_dir_all(&directory) {
+                    Ok(()) => Some(directory),
+                    Err(e) => {
+                        println!("History cache will not be saved: {e}");
+                        None
+                    }
+                }
+            }
+            _ => None,
+        };
+        HistoryCache::new(Duration::from_secs(opt.latest_version_ttl), directory)
+    }
+
+    pub fn new(latest_ttl: Duration, directory: Option<PathBuf>) -> HistoryCache {
+        HistoryCache {
+            latest_ttl,
+            directory,
+            trees: Mutex::new(HashMap::new()),
+            latest: Mutex::new(HashMap::new()),
+            published: Mutex::new(HashMap::new()),
+            archives: Mutex::new(HashMap::new()),
+            resolving: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// How long the latest version of a History is remembered
+    pub fn latest_ttl(&self) -> Duration {
+        self.latest_ttl
+    }
+
+    /// Return the version number and Tree for version of a History, or the
+    /// most recent version if version is None or Some(0)
+    ///
+    /// With no client (when offline) only what has been cached is available, and the
+    /// latest version is the most recent one resolved, however long ago. Anything else
+    /// fails with StatusCode::GATEWAY_TIMEOUT.
+    pub async fn resolve(
+        &self,
+        client: Option<&DwebClient>,
+        history_address: HistoryAddress,
+        version: Option<u32>,
+    ) -> Result<(u32, Arc<Tree>), StatusCode> {
+        let address_hex = history_address.to_hex();
+        let version = version.filter(|version| *version > 0);
+
+        if let Some(cached) = self.lookup(&address_hex, version) {
+            return Ok(cached);
+        }
+
+        let Some(client) = client else {
+            return self
+                .lookup_offline(&address_hex, version)
+                .ok_or(StatusCode::GATEWAY_TIMEOUT);
+        };
+
+        let key = (address_hex.clone(), version);
+        let lock = self.resolving_lock(&key);
+        let resolved = {
+            let _resolving = lock.lock().await;
+            // Another request may have resolved this while we waited
+            match self.lookup(&address_hex, version) {
+                Some(cached) => Ok(cached),
+                None => {
+                    self.resolve_from_network(client, history_address, address_hex, version)
+                        .await
+                }
+            }
+        };
+        self.release_resolving_lock(&key, lock);
+        resolved
+    }
+
+    fn resolving_lock(&self, key: &ResolvingKey) -> Arc<tauri::async_runtime::Mutex<()>> {
+        self.resolving
+            .lock()
+            .unwrap()
+            .entry(key.clone())
+            .or_default()
+            .clone()
+    }
+
+    /// Forget the lock for key once nobody else is waiting on it
+    fn release_resolving_lock(
+        &self,
+        key: &ResolvingKey,
+        lock: Arc<tauri::async_runtime::Mutex<()>>,
+    ) {
+        let mut resolving = self.resolving.lock().unwrap();
+        // One reference is held by the map and one by us
+        if Arc::strong_count(&lock) <= 2 {
+            resolving.remove(key);
+        }
+    }
+
+    async fn resolve_from_network(
+        &self,
+        client: &DwebClient,
+        history_address: HistoryAddress,
+        address_hex: String,
+        version: Option<u32>,
+    ) -> Result<(u32, Arc<Tree>), StatusCode> {
+        println!("DEBUG HistoryCache resolving {address_hex} version {version:?} from network");
+        let mut history = load_history(client, history_address).await?;
+
+        let Some(tree) = history.fetch_version_trove(version).await else {
+            println!("Failed to fetch version {version:?} of History {address_hex}");
+            return Err(StatusCode::NOT_FOUND);
+        };
+        let resolved_version = match history.get_cached_version() {
+            Some(trove_version) => trove_version.version,
+            None => 0,
+        };
+
+        let tree = Arc::new(tree);
+        self.save_tree(&tree_file_name(&address_hex, resolved_version), &tree);
+        self.trees
+            .lock()
+            .unwrap()
+            .insert((address_hex.clone(), resolved_version), tree.clone());
+        if version.is_none() {
+            let resolved_at = SystemTime::now();
+            self.save_latest(&address_hex, resolved_at, resolved_version);
+            self.latest
+                .lock()
+                .unwrap()
+                .insert(address_hex, (resolved_at, resolved_version));
+        }
+
+        Ok((resolved_version, tree))
+    }
+
+    /// When the latest version of a History was last resolved from the network, if ever
+    pub fn latest_resolved_at(&self, history_address: HistoryAddress) -> Option<SystemTime> {
+        let address_hex = history_address.to_hex();
+        if let Some((resolved_at, _)) = self.latest.lock().unwrap().get(&address_hex) {
+            return Some(*resolved_at);
+        }
+        self.load_latest(&address_hex)
+            .map(|(resolved_at, _)| resolved_at)
+    }
+
+    /// Return the Tree stored in the archive at address (as used by awm:// URLs)
+    ///
+    /// With no client (when offline) only a Tree fetched before is available, and
+    /// anything else is a NotCached error.
+    pub async fn archive_tree(
+        &self,
+        client: Option<&DwebClient>,
+        address: DataAddress,
+    ) -> Result<Arc<Tree>> {
+        let address_hex = address.to_hex();
+        if let Some(tree) = self.archives.lock().unwrap().get(&address_hex) {
+            return Ok(tree.clone());
+        }
+
+        let file_name = archive_file_name(&address_hex);
+        let tree = match (client, self.load_tree(&file_name)) {
+            (_, Some(tree)) => tree,
+            (Some(client), None) => {
+                let tree = Arc::new(Tree::from_archive_address(client, address).await?);
+                self.save_tree(&file_name, &tree);
+                tree
+            }
+            (None, None) => return Err(Report::new(NotCached(format!("Directory {address_hex}")))),
+        };
+        self.archives
+            .lock()
+            .unwrap()
+            .insert(address_hex, tree.clone());
+        Ok(tree)
+    }
+
+    /// Return the version number and Tree of the version of a History which was live
+    /// at time (seconds since the Unix epoch)
+    ///
+    /// This is the highest version whose entry in the History is timestamped no later than
+    /// time. Entry timestamps are set by the publisher and need not increase, so rather than
+    /// a binary search, any timestamps not already cached are fetched concurrently.
+    pub async fn resolve_at(
+        &self,
+        client: Option<&DwebClient>,
+        history_address: HistoryAddress,
+        time: u64,
+    ) -> Result<(u32, Arc<Tree>), StatusCode> {
+        let (latest_version, _) = self.resolve(client, history_address, None).await?;
+        let address_hex = history_address.to_hex();
+
+        let missing: Vec<u32> = (1..=latest_version)
+            .filter(|version| self.lookup_published(&address_hex, *version).is_none())
+            .collect();
+        if !missing.is_empty() {
+            let Some(client) = client else {
+                return Err(StatusCode::GATEWAY_TIMEOUT);
+            };
+            let history = load_history(client, history_address).await?;
+            let fetches = missing
+                .iter()
+                .map(|version| self.published_time(&history, &address_hex, *version));
+            try_join_all(fetches).await?;
+        }
+
+        let live = (1..=latest_version).rev().find(|version| {
+            self.lookup_published(&address_hex, *version)
+                .is_some_and(|published| published <= time)
+        });
+        match live {
+            Some(version) => self.resolve(client, history_address, Some(version)).await,
+            None => Err(StatusCode::NOT_FOUND),
+        }
+    }
+
+    /// Return when version of history was published (seconds since the Unix epoch), which
+    /// is the timestamp of its entry in the History rather than anything in its files
+    pub async fn published_time(
+        &self,
+        history: &History<Tree>,
+        address_hex: &String,
+        version: u32,
+    ) -> Result<u64, StatusCode> {
+        if let Some(published) = self.lookup_published(address_hex, version) {
+            return Ok(published);
+        }
+        let published = match history.get_version_timestamp(version).await {
+            Ok(published) => published,
+            Err(e) => {
+                println!("Failed to get timestamp of version {version} of {address_hex}: {e}");
+                return Err(StatusCode::NOT_FOUND);
+            }
+        };
+        self.save_published(address_hex, version, published);
+        self.published
+            .lock()
+            .unwrap()
+            .insert((address_hex.clone(), version), published);
+        Ok(published)
+    }
+
+    fn lookup(&self, address_hex: &String, version: Option<u32>) -> Option<(u32, Arc<Tree>)> {
+        let version = match version {
+            Some(version) => version,
+            None => {
+                let latest = self.latest.lock().unwrap();
+                let (resolved_at, version) = latest.get(address_hex)?;
+                let age = resolved_at.elapsed().unwrap_or(Duration::ZERO);
+                if age > self.latest_ttl {
+                    return None;
+                }
+                *version
+            }
+        };
+
+        let trees = self.trees.lock().unwrap();
+        let tree = trees.get(&(address_hex.clone(), version))?;
+        Some((version, tree.clone()))
+    }
+
+    /// Return when version was published if known, including from what was saved on disk
+    fn lookup_published(&self, address_hex: &String, version: u32) -> Option<u64> {
+        let key = (address_hex.clone(), version);
+        if let Some(published) = self.published.lock().unwrap().get(&key) {
+            return Some(*published);
+        }
+        let published = self.load_published(address_hex, version)?;
+        self.published.lock().unwrap().insert(key, published);
+        Some(published)
+    }
+
+    /// Like lookup() but ignoring the time to live, and also using what was saved on disk
+    fn lookup_offline(
+        &self,
+        address_hex: &String,
+        version: Option<u32>,
+    ) -> Option<(u32, Arc<Tree>)> {
+        let version = match version {
+            Some(version) => version,
+            None => {
+                let latest = self.latest.lock().unwrap().get(address_hex).copied();
+                let (resolved_at, version) = latest.or_else(|| self.load_latest(address_hex))?;
+                self.latest
+                    .lock()
+                    .unwrap()
+                    .entry(address_hex.clone())
+                    .or_insert((resolved_at, version));
+                version
+            }
+        };
+
+        let key = (address_hex.clone(), version);
+        if let Some(tree) = self.trees.lock().unwrap().get(&key) {
+            return Some((version, tree.clone()));
+        }
+        let tree = self.load_tree(&tree_file_name(address_hex, version))?;
+        self.trees.lock().unwrap().insert(key, tree.clone());
+        Some((version, tree))
+    }
+
+    fn save_tree(&self, file_name: &str, tree: &Tree) {
+        let Some(directory) = &self.directory else {
+            return;
+        };
+        let result = tree
+            .archive
+            .to_bytes()
+            .map_err(|e| std::io::Error::other(e.to_string()))
+            .and_then(|bytes| fs::write(directory.join(file_name), bytes));
+        if let Err(e) = result {
+            println!("DEBUG failed to save {file_name} to history cache: {e}");
+        }
+    }
+
+    fn load_tree(&self, file_name: &str) -> Option<Arc<Tree>> {
+        let bytes = fs::read(self.directory.as_ref()?.join(file_name)).ok()?;
+        match PublicArchive::from_bytes(Bytes::from(bytes)) {
+            Ok(archive) => Some(Arc::new(Tree::from_archive(archive))),
+            Err(e) => {
+                println!("DEBUG ignoring {file_name} in history cache: {e}");
+                None
+            }
+        }
+    }
+
+    fn save_latest(&self, address_hex: &str, resolved_at: SystemTime, version: u32) {
+        let Some(directory) = &self.directory else {
+            return;
+        };
+        let seconds = resolved_at
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or(Duration::ZERO)
+            .as_secs();
+        let path = directory.join(latest_file_name(address_hex));
+        if let Err(e) = fs::write(path, format!("{version} {seconds}")) {
+            println!("DEBUG failed to save latest version of {address_hex}: {e}");
+        }
+    }
+
+    fn load_latest(&self, address_hex: &str) -> Option<(SystemTime, u32)> {
+        let path = self.directory.as_ref()?.join(latest_file_name(address_hex));
+        let text = fs::read_to_string(path).ok()?;
+        let (version, seconds) = text.trim().split_once(' ')?;
+        let resolved_at = SystemTime::UNIX_EPOCH + Duration::from_secs(seconds.parse().ok()?);
+        Some((resolved_at, version.parse().ok()?))
+    }
+
+    fn save_published(&self, address_hex: &str, version: u32, published: u64) {
+        let Some(directory) = &self.directory else {
+            return;
+        };
+        let path = directory.join(published_file_name(address_hex, version));
+        if let Err(e) = fs::write(path, published.to_string()) {
+            println!("DEBUG failed to save publish time of {address_hex} version {version}: {e}");
+        }
+    }
+
+    fn load_published(&self, address_hex: &str, version: u32) -> Option<u64> {
+        let path = self
+            .directory
+            .as_ref()?
+            .join(published_file_name(address_hex, version));
+        fs::read_to_string(path).ok()?.trim().parse().ok()
+    }
+}
+
+async fn load_history(
+    client: &DwebClient,
+    history_address: HistoryAddress,
+) -> Result<History<Tree>, StatusCode> {
+    match History::<Tree>::from_history_address(client.clone(), history_address, false, 0).await {
+        Ok(history) => Ok(history),
+        Err(e) => {
+            println!("Failed to load History: {e:?}");
+            Err(StatusCode::NOT_FOUND)
+        }
+    }
+}
+
+fn tree_file_name(address_hex: &str, version: u32) -> String {
+    format!("tree-{address_hex}-{version}")
+}
+
+fn published_file_name(address_hex: &str, version: u32) -> String {
+    format!("published-{address_hex}-{version}")
+}
+
+fn latest_file_name(address_hex: &str) -> String {
+    format!("latest-{address_hex}")
+}
+
+fn archive_file_name(address_hex: &str) -> String {
+    format!("archive-{address_hex}")
+}
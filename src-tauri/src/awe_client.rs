@@ -16,20 +16,103 @@ You should have received a copy of the GNU Affero General Public License
 along with this program. If not, see <https://www.gnu.org/licenses/>.
 */
 
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
 use autonomi::data::DataAddress;
 use bytes::Bytes;
 use color_eyre::eyre::{eyre, Result};
+use tauri::async_runtime::Mutex;
 use xor_name::XorName;
 
 use autonomi::PointerAddress as HistoryAddress;
 
 use dweb::client::{ApiControl, DwebClient};
-use dweb::helpers::convert::str_to_pointer_address;
 
-use crate::awe_protocols::{AWE_PROTOCOL_DIRECTORY, AWE_PROTOCOL_FILE, AWE_PROTOCOL_HISTORY};
 use crate::awe_subcommands::connect_and_announce;
+use crate::awe_url::{AweScheme, AweUrl};
+
+/// How long to serve from the cache after failing to connect, before trying again
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// A DwebClient shared by all protocol handlers and held in Tauri state
+///
+/// The connection is made once (at startup) and the same client is handed to
+/// every request. If a request fails because of the network, the handler calls
+/// reset() so that the next request makes a fresh connection.
+///
+/// When offline (set with --offline, or after failing to connect) there is no
+/// client and the handlers serve only what they have cached.
+#[derive(Clone, Default)]
+pub struct SharedClient {
+    client: Arc<Mutex<Option<DwebClient>>>,
+    offline: bool,
+    /// When an attempt to connect last failed
+    failed_at: Arc<std::sync::Mutex<Option<Instant>>>,
+}
+
+impl SharedClient {
+    /// Create a SharedClient which is offline if --offline was given on the CLI
+    pub fn new_default() -> SharedClient {
+        use crate::cli_options::Opt;
+        use clap::Parser;
+        let opt = Opt::parse();
+
+        SharedClient {
+            offline: opt.offline,
+            ..Default::default()
+        }
+    }
+
+    /// True if set offline on the CLI, so no connection is ever made
+    pub fn is_offline(&self) -> bool {
+        self.offline
+    }
+
+    /// Return the shared client, connecting first if there is no live connection
+    ///
+    /// Concurrent callers wait on the same connection attempt rather than each
+    /// making their own. After a failed attempt, requests fail straight away until
+    /// RECONNECT_INTERVAL has passed so that they can be served from the cache.
+    pub async fn get(&self) -> Result<DwebClient> {
+        if self.offline {
+            return Err(eyre!("Offline (--offline was given)"));
+        }
+
+        let mut client = self.client.lock().await;
+        if let Some(client) = client.as_ref() {
+            return Ok(client.clone());
+        }
+
+        if let Some(failed_at) = *self.failed_at.lock().unwrap() {
+            if failed_at.elapsed() < RECONNECT_INTERVAL {
+                return Err(eyre!(
+                    "Unable to connect to Autonomi Network, will retry shortly"
+                ));
+            }
+        }
+
+        match connect_to_autonomi().await {
+            Ok(new_client) => {
+                *self.failed_at.lock().unwrap() = None;
+                *client = Some(new_client.clone());
+                Ok(new_client)
+            }
+            Err(e) => {
+                *self.failed_at.lock().unwrap() = Some(Instant::now());
+                Err(e)
+            }
+        }
+    }
+
+    /// Drop the current connection so the next call to get() reconnects
+    pub async fn reset(&self) {
+        println!("DEBUG SharedClient::reset() - will reconnect on next request");
+        *self.client.lock().await = None;
+    }
+}
 
-/// Fallback for use by awe protocol handlers
+/// Make a new connection using the CLI options (see SharedClient for use by protocol handlers)
 pub async fn connect_to_autonomi() -> Result<DwebClient> {
     use crate::cli_options::Opt;
     use clap::Parser;
@@ -44,7 +127,7 @@ pub async fn connect_to_autonomi() -> Result<DwebClient> {
     };
 
     let (client, _is_local_network) =
-        connect_and_announce(opt.local, opt.alpha, api_control, true).await;
+        connect_and_announce(opt.local, opt.alpha, api_control, true).await?;
     Ok(client)
 }
 
@@ -75,53 +158,15 @@ pub async fn autonomi_get_file_public(
 
 /// Parse a hex HistoryAddress with optional URL scheme
 pub fn awe_str_to_history_address(str: &str) -> Result<HistoryAddress> {
-    let str = if str.starts_with(AWE_PROTOCOL_HISTORY) {
-        &str[AWE_PROTOCOL_HISTORY.len()..]
-    } else {
-        &str
-    };
-
-    match str_to_pointer_address(str) {
-        Ok(history_address) => Ok(history_address),
-        Err(e) => Err(eyre!(
-            "Invalid History (pointer) address string '{str}':\n{e:?}"
-        )),
-    }
+    AweUrl::parse_with_default_scheme(str, AweScheme::History)?.history_address()
 }
 
 /// Parse a hex PointerAddress with optional URL scheme
 pub fn awe_str_to_pointer_address(str: &str) -> Result<HistoryAddress> {
-    let str = if str.starts_with(AWE_PROTOCOL_HISTORY) {
-        &str[AWE_PROTOCOL_HISTORY.len()..]
-    } else {
-        &str
-    };
-
-    match str_to_pointer_address(str) {
-        Ok(pointer_address) => Ok(pointer_address),
-        Err(e) => Err(eyre!("Invalid pointer address string '{str}':\n{e:?}")),
-    }
+    awe_str_to_history_address(str)
 }
 
+/// Parse a hex XorName with optional awm:// or awf:// URL scheme
 pub fn awe_str_to_xor_name(str: &str) -> Result<XorName> {
-    let str = if str.starts_with(AWE_PROTOCOL_DIRECTORY) {
-        &str[AWE_PROTOCOL_DIRECTORY.len()..]
-    } else if str.starts_with(AWE_PROTOCOL_FILE) {
-        &str[AWE_PROTOCOL_FILE.len()..]
-    } else {
-        &str
-    };
-    let str = if str.ends_with('/') {
-        &str[0..str.len() - 1]
-    } else {
-        str
-    };
-
-    match hex::decode(str) {
-        Ok(bytes) => match bytes.try_into() {
-            Ok(xor_name_bytes) => Ok(XorName(xor_name_bytes)),
-            Err(e) => Err(eyre!("XorName not valid due to {e:?}")),
-        },
-        Err(e) => Err(eyre!("XorName not valid due to {e:?}")),
-    }
+    AweUrl::parse_with_default_scheme(str, AweScheme::Directory)?.xor_name()
 }
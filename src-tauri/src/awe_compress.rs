@@ -0,0 +1,278 @@
+/*
+Copyright (c) 2024-2025 Mark Hughes
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+//! Compressed copies of website files, made when publishing with --compress
+//!
+//! Each copy is stored alongside the original as '<path>.br' or '<path>.gz' and is
+//! served instead of the original to a webview which accepts that encoding. The copies
+//! are listed in /_encodings so that serving a file needs no search for them.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::{eyre, Result};
+use flate2::write::GzEncoder;
+use serde::{Deserialize, Serialize};
+use tempfile::TempDir;
+use walkdir::WalkDir;
+
+use crate::awe_http::ContentEncoding;
+
+/// Lists the compressed copies in a published site
+pub const COMPRESSED_COPIES_FILE: &str = "/_encodings";
+
+/// The files served for a directory, in order of preference
+const INDEX_FILES: [&str; 2] = ["index.html", "index.htm"];
+
+/// Files worth compressing, which are mostly text
+const COMPRESSIBLE_EXTENSIONS: [&str; 14] = [
+    "html",
+    "htm",
+    "css",
+    "js",
+    "mjs",
+    "json",
+    "map",
+    "svg",
+    "txt",
+    "xml",
+    "wasm",
+    "csv",
+    "md",
+    "webmanifest",
+];
+
+/// Smaller files gain too little to be worth a copy
+const MIN_COMPRESS_SIZE: u64 = 1024;
+
+const BROTLI_BUFFER_SIZE: usize = 4096;
+const BROTLI_QUALITY: u32 = 11;
+const BROTLI_WINDOW_BITS: u32 = 22;
+
+/// The compressed copies added by stage_with_compressed_copies
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct CompressionSummary {
+    pub files_compressed: usize,
+    /// Total size of the files compressed
+    pub original_bytes: u64,
+    /// Total size of the smallest copy of each
+    pub compressed_bytes: u64,
+}
+
+/// The contents of _encodings
+///
+/// For example:
+///
+///   ["/app.js"]
+///   br = "/app.js.br"
+///   gzip = "/app.js.gz"
+///
+///   ["/docs"]
+///   br = "/docs/index.html.br"
+///
+/// Each path served from a compressed copy has the path of its copy in each encoding,
+/// keyed by Content-Encoding. A directory is listed as well as its index file.
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(transparent)]
+pub struct CompressedCopies {
+    copies: BTreeMap<String, BTreeMap<String, String>>,
+}
+
+impl CompressedCopies {
+    /// Parse the text of _encodings
+    pub fn from_text(text: &str) -> Result<CompressedCopies, String> {
+        toml::from_str::<CompressedCopies>(text).map_err(|e| format!("{e}"))
+    }
+
+    /// Return the first of encodings in which there is a copy of the file at path,
+    /// and the path of the copy
+    pub fn copy_for(
+        &self,
+        path: &str,
+        encodings: &[ContentEncoding],
+    ) -> Option<(ContentEncoding, String)> {
+        let copies = self.copies.get(copies_key(path))?;
+        encodings.iter().find_map(|encoding| {
+            copies
+                .get(encoding.header_value())
+                .map(|copy_path| (*encoding, copy_path.clone()))
+        })
+    }
+
+    fn add(&mut self, path: &str, encoding: ContentEncoding, copy_path: &str) {
+        self.copies
+            .entry(copies_key(path).to_string())
+            .or_default()
+            .insert(encoding.header_value().to_string(), copy_path.to_string());
+    }
+}
+
+/// Copy the directory tree at files_root to a temporary directory, adding a compressed
+/// copy of each compressible file which is made noticeably smaller by compression.
+///
+/// Copies already present in files_root (e.g. made by a site generator) are kept as they are.
+/// All copies are listed in _encodings, which is added to the root of the directory.
+/// Modification times are preserved so the published metadata matches files_root.
+/// The returned directory is deleted when dropped.
+pub fn stage_with_compressed_copies(files_root: &Path) -> Result<(TempDir, CompressionSummary)> {
+    let staging = tempfile::tempdir()?;
+    let mut summary = CompressionSummary::default();
+    let mut copies = CompressedCopies::default();
+
+    for entry in WalkDir::new(files_root).follow_links(true) {
+        let entry = entry?;
+        let relative_path = entry.path().strip_prefix(files_root)?;
+        let staged_path = staging.path().join(relative_path);
+        if entry.file_type().is_dir() {
+            fs::create_dir_all(&staged_path)?;
+            continue;
+        }
+
+        // Written rather than copied so that a read-only file can have its time set
+        let metadata = entry.metadata()?;
+        let content = fs::read(entry.path())?;
+        fs::write(&staged_path, &content)?;
+        set_modified(&staged_path, &metadata)?;
+
+        if !is_compressible(entry.path()) || metadata.len() < MIN_COMPRESS_SIZE {
+            continue;
+        }
+
+        // A directory is served from its index, so its copies are listed under both
+        let file_path = tree_path(relative_path);
+        let mut served_paths = vec![file_path.clone()];
+        if is_directory_index(entry.path()) {
+            if let Some((directory, _)) = file_path.rsplit_once('/') {
+                served_paths.push(directory.to_string());
+            }
+        }
+
+        let mut best_size = None;
+        for encoding in ContentEncoding::ALL {
+            let copy_path = format!("{file_path}{}", encoding.extension());
+            if path_with_extension(entry.path(), encoding).exists() {
+                for path in &served_paths {
+                    copies.add(path, encoding, &copy_path);
+                }
+                continue;
+            }
+            let compressed = compress(&content, encoding)?;
+            // Not worth storing unless it saves at least a tenth
+            if compressed.len() as u64 * 10 > metadata.len() * 9 {
+                continue;
+            }
+            let staged_copy_path = path_with_extension(&staged_path, encoding);
+            fs::write(&staged_copy_path, &compressed)?;
+            set_modified(&staged_copy_path, &metadata)?;
+            for path in &served_paths {
+                copies.add(path, encoding, &copy_path);
+            }
+            let size = compressed.len() as u64;
+            best_size = Some(best_size.map_or(size, |best: u64| best.min(size)));
+        }
+
+        if let Some(best_size) = best_size {
+            summary.files_compressed += 1;
+            summary.original_bytes += metadata.len();
+            summary.compressed_bytes += best_size;
+        }
+    }
+
+    if !copies.copies.is_empty() {
+        let copies_path = staging
+            .path()
+            .join(COMPRESSED_COPIES_FILE.trim_start_matches('/'));
+        fs::write(copies_path, toml::to_string(&copies)?)?;
+    }
+
+    Ok((staging, summary))
+}
+
+/// Compress content using encoding
+pub fn compress(content: &[u8], encoding: ContentEncoding) -> Result<Vec<u8>> {
+    match encoding {
+        ContentEncoding::Brotli => {
+            let mut writer = brotli::CompressorWriter::new(
+                Vec::new(),
+                BROTLI_BUFFER_SIZE,
+                BROTLI_QUALITY,
+                BROTLI_WINDOW_BITS,
+            );
+            writer.write_all(content)?;
+            Ok(writer.into_inner())
+        }
+        ContentEncoding::Gzip => {
+            let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::best());
+            encoder.write_all(content)?;
+            Ok(encoder.finish()?)
+        }
+    }
+}
+
+fn is_compressible(path: &Path) -> bool {
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some(extension) => {
+            COMPRESSIBLE_EXTENSIONS.contains(&extension.to_ascii_lowercase().as_str())
+        }
+        None => false,
+    }
+}
+
+/// Return true if the file at path is the one served for its directory
+fn is_directory_index(path: &Path) -> bool {
+    let (Some(directory), Some(name)) = (path.parent(), path.file_name()) else {
+        return false;
+    };
+    INDEX_FILES
+        .iter()
+        .find(|index| directory.join(index).is_file())
+        .is_some_and(|index| name.to_str() == Some(*index))
+}
+
+/// The path in the published tree of the file at relative_path, such as '/docs/index.html'
+fn tree_path(relative_path: &Path) -> String {
+    let components: Vec<String> = relative_path
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy().to_string())
+        .collect();
+    format!("/{}", components.join("/"))
+}
+
+/// The key of path in CompressedCopies, which ignores any trailing '/'
+fn copies_key(path: &str) -> &str {
+    match path.trim_end_matches('/') {
+        "" => "/",
+        key => key,
+    }
+}
+
+fn path_with_extension(path: &Path, encoding: ContentEncoding) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(encoding.extension());
+    PathBuf::from(path)
+}
+
+fn set_modified(path: &Path, metadata: &fs::Metadata) -> Result<()> {
+    let modified = metadata.modified()?;
+    fs::File::options()
+        .write(true)
+        .open(path)
+        .and_then(|file| file.set_modified(modified))
+        .map_err(|e| eyre!("Failed to set modification time of {path:?}: {e}"))
+}
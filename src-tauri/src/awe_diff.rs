@@ -0,0 +1,283 @@
+/*
+Copyright (c) 2024-2025 Mark Hughes
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+//! Comparison of two versions of a website
+
+use std::collections::BTreeMap;
+
+use color_eyre::eyre::Result;
+use serde::Serialize;
+use similar::TextDiff;
+
+use dweb::client::DwebClient;
+use dweb::files::directory::{datamap_and_address_from_hex, Tree};
+use dweb::history::HistoryAddress;
+
+use crate::awe_cache::{get_content_cached, ContentCache, HistoryCache};
+use crate::awe_errors::ProtocolError;
+
+/// Text files larger than this are compared by address and size only
+const MAX_TEXT_DIFF_SIZE: u64 = 1024 * 1024;
+
+/// Lines of unchanged text shown around each change in a text diff
+const TEXT_DIFF_CONTEXT: usize = 3;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Change {
+    Added,
+    Removed,
+    Modified,
+    Unchanged,
+    /// In both versions, but the content of one couldn't be identified to compare
+    Unknown,
+}
+
+impl Change {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Change::Added => "added",
+            Change::Removed => "removed",
+            Change::Modified => "modified",
+            Change::Unchanged => "unchanged",
+            Change::Unknown => "unknown",
+        }
+    }
+}
+
+/// How one path differs between two versions
+#[derive(Clone, Debug, Serialize)]
+pub struct PathChange {
+    pub path: String,
+    pub change: Change,
+    /// Size in bytes in the 'from' version, if present
+    pub from_size: Option<u64>,
+    /// Size in bytes in the 'to' version, if present
+    pub to_size: Option<u64>,
+    pub size_delta: i64,
+    /// A unified diff, only for modified text files when asked for
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text_diff: Option<String>,
+}
+
+/// The differences between two versions of a website, sorted by path
+#[derive(Clone, Debug, Serialize)]
+pub struct VersionDiff {
+    pub history_address: String,
+    pub from_version: u32,
+    pub to_version: u32,
+    pub changes: Vec<PathChange>,
+}
+
+impl VersionDiff {
+    /// The number of paths with each kind of change
+    pub fn count(&self, change: Change) -> usize {
+        self.changes
+            .iter()
+            .filter(|path_change| path_change.change == change)
+            .count()
+    }
+
+    /// The total change in size of all files in bytes
+    pub fn size_delta(&self) -> i64 {
+        self.changes
+            .iter()
+            .map(|path_change| path_change.size_delta)
+            .sum()
+    }
+}
+
+/// Compare two versions of the website at history_address
+///
+/// Files are compared by address, so a file is only modified if its content changed. With
+/// text_diffs, modified text files are fetched (or taken from content_cache) to add a
+/// unified diff of their lines.
+pub async fn diff_versions(
+    client: Option<&DwebClient>,
+    history_cache: &HistoryCache,
+    content_cache: Option<&ContentCache>,
+    history_address: HistoryAddress,
+    from_version: u32,
+    to_version: u32,
+    text_diffs: bool,
+) -> Result<VersionDiff> {
+    let address_hex = history_address.to_hex();
+    let (_, from_tree) = history_cache
+        .resolve(client, history_address, Some(from_version))
+        .await
+        .map_err(|status| {
+            ProtocolError::from_status(
+                status,
+                format!("Failed to fetch version {from_version}: {status}"),
+            )
+        })?;
+    let (_, to_tree) = history_cache
+        .resolve(client, history_address, Some(to_version))
+        .await
+        .map_err(|status| {
+            ProtocolError::from_status(
+                status,
+                format!("Failed to fetch version {to_version}: {status}"),
+            )
+        })?;
+
+    let mut changes = diff_trees(&from_tree, &to_tree);
+    if text_diffs {
+        for path_change in changes.iter_mut() {
+            if path_change.change == Change::Modified && is_text(path_change) {
+                path_change.text_diff = text_diff(
+                    client,
+                    content_cache,
+                    &from_tree,
+                    &to_tree,
+                    &path_change.path,
+                )
+                .await;
+            }
+        }
+    }
+
+    Ok(VersionDiff {
+        history_address: address_hex,
+        from_version,
+        to_version,
+        changes,
+    })
+}
+
+/// Compare the files of two trees, sorted by path
+pub fn diff_trees(from_tree: &Tree, to_tree: &Tree) -> Vec<PathChange> {
+    let from_files = files_of(from_tree);
+    let mut to_files = files_of(to_tree);
+
+    let mut changes = Vec::new();
+    for (path, (from_address, from_size)) in from_files {
+        let (change, to_size) = match to_files.remove(&path) {
+            Some((to_address, to_size)) => match (&from_address, &to_address) {
+                (Some(from_address), Some(to_address)) if from_address == to_address => {
+                    (Change::Unchanged, Some(to_size))
+                }
+                (Some(_), Some(_)) => (Change::Modified, Some(to_size)),
+                _ => (Change::Unknown, Some(to_size)),
+            },
+            None => (Change::Removed, None),
+        };
+        changes.push(path_change(path, change, Some(from_size), to_size));
+    }
+    for (path, (_, to_size)) in to_files {
+        changes.push(path_change(path, Change::Added, None, Some(to_size)));
+    }
+
+    changes.sort_by(|a, b| a.path.cmp(&b.path));
+    changes
+}
+
+fn path_change(
+    path: String,
+    change: Change,
+    from_size: Option<u64>,
+    to_size: Option<u64>,
+) -> PathChange {
+    PathChange {
+        path,
+        change,
+        from_size,
+        to_size,
+        size_delta: to_size.unwrap_or(0) as i64 - from_size.unwrap_or(0) as i64,
+        text_diff: None,
+    }
+}
+
+/// Return the content key and size of each file in tree, keyed by path
+///
+/// The key is None if the file's content can't be identified, so that the file is still
+/// reported (as Change::Unknown if it is in both versions) rather than left out.
+fn files_of(tree: &Tree) -> BTreeMap<String, (Option<String>, u64)> {
+    let mut files = BTreeMap::new();
+    for (path, _datamap_chunk, metadata) in tree.archive.iter() {
+        let path = path.to_string_lossy().replace('\\', "/");
+        let key = match tree.lookup_file(&path, false) {
+            Ok((datamap_chunk, data_address, _)) => {
+                let (datamap_chunk, data_address) =
+                    datamap_and_address_from_hex(datamap_chunk, data_address);
+                ContentCache::key_for(&datamap_chunk, &data_address)
+            }
+            Err(status) => {
+                eprintln!("Unable to identify content of {path}: {status}");
+                None
+            }
+        };
+        files.insert(path, (key, metadata.size));
+    }
+    files
+}
+
+fn is_text(path_change: &PathChange) -> bool {
+    if path_change.from_size.unwrap_or(0) > MAX_TEXT_DIFF_SIZE
+        || path_change.to_size.unwrap_or(0) > MAX_TEXT_DIFF_SIZE
+    {
+        return false;
+    }
+    match mime_guess::from_path(&path_change.path).first() {
+        Some(mime) => {
+            mime.type_() == "text"
+                || ["json", "javascript", "xml", "svg"]
+                    .iter()
+                    .any(|subtype| mime.subtype().as_str().contains(subtype))
+        }
+        None => false,
+    }
+}
+
+/// Return a unified diff of the file at path in each tree, or None if either
+/// can't be fetched or is not UTF-8 text
+async fn text_diff(
+    client: Option<&DwebClient>,
+    content_cache: Option<&ContentCache>,
+    from_tree: &Tree,
+    to_tree: &Tree,
+    path: &String,
+) -> Option<String> {
+    let from_text = text_content(client, content_cache, from_tree, path).await?;
+    let to_text = text_content(client, content_cache, to_tree, path).await?;
+    let diff = TextDiff::from_lines(&from_text, &to_text)
+        .unified_diff()
+        .context_radius(TEXT_DIFF_CONTEXT)
+        .header(
+            &format!("a/{}", path.trim_start_matches('/')),
+            &format!("b/{}", path.trim_start_matches('/')),
+        )
+        .to_string();
+    Some(diff)
+}
+
+async fn text_content(
+    client: Option<&DwebClient>,
+    content_cache: Option<&ContentCache>,
+    tree: &Tree,
+    path: &String,
+) -> Option<String> {
+    let (datamap_chunk, data_address, _) = tree.lookup_file(path, false).ok()?;
+    let (datamap_chunk, data_address) = datamap_and_address_from_hex(datamap_chunk, data_address);
+    match get_content_cached(client, content_cache, datamap_chunk, data_address).await {
+        Ok(content) => String::from_utf8(content.to_vec()).ok(),
+        Err(e) => {
+            eprintln!("Failed to fetch {path} for text diff: {e}");
+            None
+        }
+    }
+}
@@ -0,0 +1,346 @@
+/*
+Copyright (c) 2024-2025 Mark Hughes
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use color_eyre::eyre::Report;
+use http::{header, status::StatusCode};
+use thiserror::Error;
+
+use autonomi::client::payment::PayError;
+use autonomi::client::{GetError, PutError};
+use evmlib::wallet::Error as WalletError;
+
+use crate::awe_protocols::tauri_http_status_from_network_error;
+
+/// Failures of the awe protocol handlers, each of which is shown as an error page
+#[derive(Debug, Error)]
+pub enum ProtocolError {
+    #[error("{0}")]
+    BadRequest(String),
+    #[error("{0}")]
+    BadAddress(String),
+    #[error("{0}")]
+    NotFound(String),
+    #[error("{0}")]
+    NetworkUnavailable(String),
+    #[error("{0}")]
+    Deserialisation(String),
+    #[error("{0}")]
+    NotAvailableOffline(String),
+    #[error("{1}")]
+    Other(StatusCode, String),
+}
+
+impl ProtocolError {
+    /// Classify an error returned when fetching data from the network
+    ///
+    /// Errors from the Autonomi API are mapped using tauri_http_status_from_network_error()
+    pub fn from_fetch_error(error: &Report) -> ProtocolError {
+        if error.downcast_ref::<NotCached>().is_some() {
+            return ProtocolError::NotAvailableOffline(format!("{error}"));
+        }
+        match error.downcast_ref::<GetError>() {
+            Some(get_error) => {
+                let (status, message) = tauri_http_status_from_network_error(get_error);
+                let message = format!("{message}: {error}");
+                ProtocolError::from_status(status, message)
+            }
+            None => ProtocolError::NetworkUnavailable(format!("{error}")),
+        }
+    }
+
+    /// Classify a failure given as an http status (e.g. from a Tree lookup)
+    pub fn from_status(status: StatusCode, message: String) -> ProtocolError {
+        match status {
+            StatusCode::BAD_REQUEST => ProtocolError::BadRequest(message),
+            StatusCode::NOT_FOUND => ProtocolError::NotFound(message),
+            StatusCode::SERVICE_UNAVAILABLE | StatusCode::BAD_GATEWAY => {
+                ProtocolError::NetworkUnavailable(message)
+            }
+            StatusCode::INTERNAL_SERVER_ERROR => ProtocolError::Deserialisation(message),
+            StatusCode::GATEWAY_TIMEOUT => ProtocolError::NotAvailableOffline(message),
+            status => ProtocolError::Other(status, message),
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ProtocolError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ProtocolError::BadAddress(_) => StatusCode::BAD_REQUEST,
+            ProtocolError::NotFound(_) => StatusCode::NOT_FOUND,
+            ProtocolError::NetworkUnavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            ProtocolError::Deserialisation(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            // As for a cache asked for 'only-if-cached' content it doesn't hold (RFC 9111)
+            ProtocolError::NotAvailableOffline(_) => StatusCode::GATEWAY_TIMEOUT,
+            ProtocolError::Other(status, _) => *status,
+        }
+    }
+
+    /// A short description of the kind of failure, for the error page
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ProtocolError::BadRequest(_) => "The URL could not be understood",
+            ProtocolError::BadAddress(_) => "The address in the URL is not valid",
+            ProtocolError::NotFound(_) => "Nothing was found at this address",
+            ProtocolError::NetworkUnavailable(_) => "The Autonomi network is not available",
+            ProtocolError::Deserialisation(_) => "The data found could not be decoded",
+            ProtocolError::NotAvailableOffline(_) => {
+                "Not available offline, as this has not been fetched from the network before"
+            }
+            ProtocolError::Other(_, _) => "The request failed",
+        }
+    }
+
+    /// Render this error as an HTML error page for url
+    pub fn to_response(&self, url: &str) -> http::Response<Vec<u8>> {
+        println!("{} ({}) for {url}: {self}", self.status(), self.kind());
+        http::Response::builder()
+            .status(self.status())
+            .header(header::CONTENT_TYPE, "text/html; charset=utf-8")
+            .header(header::CACHE_CONTROL, "no-store")
+            .body(error_page(self.status(), url, self.kind(), &self.to_string()).into_bytes())
+            .unwrap()
+    }
+}
+
+/// Content which is not in the local cache while offline
+#[derive(Debug, Error)]
+#[error("{0} is not in the local cache")]
+pub struct NotCached(pub String);
+
+/// Failures of CLI subcommands, each of which ends the process with its own exit code
+///
+/// Exit code 2 is left for the command line usage errors reported by clap.
+#[derive(Debug, Error)]
+pub enum CliError {
+    #[error("{0}")]
+    Connection(String),
+    #[error("{0}")]
+    InsufficientFunds(String),
+    #[error("{0}")]
+    BadAddress(String),
+    #[error("{0}")]
+    NotFound(String),
+    #[error("{0}")]
+    PartialUpload(String),
+    #[error("{0}")]
+    Other(String),
+}
+
+impl CliError {
+    /// Classify the error which ended a subcommand by the first error in its chain
+    /// whose type says what went wrong
+    pub fn from_report(error: &Report) -> CliError {
+        let message = format!("{error}");
+        for cause in error.chain() {
+            if let Some(cli_error) = cause.downcast_ref::<CliError>() {
+                return CliError::from_kind(cli_error, message);
+            }
+            if let Some(protocol_error) = cause.downcast_ref::<ProtocolError>() {
+                return CliError::from_protocol_error(protocol_error, message);
+            }
+            if let Some(get_error) = cause.downcast_ref::<GetError>() {
+                let (status, _) = tauri_http_status_from_network_error(get_error);
+                let protocol_error = ProtocolError::from_status(status, String::from(""));
+                return CliError::from_protocol_error(&protocol_error, message);
+            }
+            if cause.downcast_ref::<NotCached>().is_some() {
+                return CliError::NotFound(message);
+            }
+            if let Some(PutError::Batch(_)) = cause.downcast_ref::<PutError>() {
+                return CliError::PartialUpload(message);
+            }
+            if let Some(PayError::EvmWalletError(wallet_error)) = cause.downcast_ref::<PayError>() {
+                if is_insufficient_funds(wallet_error) {
+                    return CliError::InsufficientFunds(message);
+                }
+            }
+            if let Some(wallet_error) = cause.downcast_ref::<WalletError>() {
+                if is_insufficient_funds(wallet_error) {
+                    return CliError::InsufficientFunds(message);
+                }
+            }
+        }
+        CliError::Other(message)
+    }
+
+    fn from_kind(cli_error: &CliError, message: String) -> CliError {
+        match cli_error {
+            CliError::Connection(_) => CliError::Connection(message),
+            CliError::InsufficientFunds(_) => CliError::InsufficientFunds(message),
+            CliError::BadAddress(_) => CliError::BadAddress(message),
+            CliError::NotFound(_) => CliError::NotFound(message),
+            CliError::PartialUpload(_) => CliError::PartialUpload(message),
+            CliError::Other(_) => CliError::Other(message),
+        }
+    }
+
+    fn from_protocol_error(protocol_error: &ProtocolError, message: String) -> CliError {
+        match protocol_error {
+            ProtocolError::BadRequest(_) | ProtocolError::BadAddress(_) => {
+                CliError::BadAddress(message)
+            }
+            ProtocolError::NotFound(_) | ProtocolError::NotAvailableOffline(_) => {
+                CliError::NotFound(message)
+            }
+            ProtocolError::NetworkUnavailable(_) => CliError::Connection(message),
+            ProtocolError::Deserialisation(_) | ProtocolError::Other(_, _) => {
+                CliError::Other(message)
+            }
+        }
+    }
+
+    /// The exit code of the process
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            CliError::Other(_) => 1,
+            CliError::Connection(_) => 3,
+            CliError::InsufficientFunds(_) => 4,
+            CliError::BadAddress(_) => 5,
+            CliError::NotFound(_) => 6,
+            CliError::PartialUpload(_) => 7,
+        }
+    }
+
+    /// A short name for the kind of failure, as output with '--output json'
+    pub fn kind(&self) -> &'static str {
+        match self {
+            CliError::Connection(_) => "connection",
+            CliError::InsufficientFunds(_) => "insufficient-funds",
+            CliError::BadAddress(_) => "bad-address",
+            CliError::NotFound(_) => "not-found",
+            CliError::PartialUpload(_) => "partial-upload",
+            CliError::Other(_) => "other",
+        }
+    }
+}
+
+fn is_insufficient_funds(wallet_error: &WalletError) -> bool {
+    matches!(wallet_error, WalletError::InsufficientTokensForQuotes(_, _))
+}
+
+/// Return an HTML page describing a failure to load url
+pub fn error_page(status: StatusCode, url: &str, kind: &str, detail: &str) -> String {
+    let reason = status.canonical_reason().unwrap_or("Error");
+    let url = html_escape(url);
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>{code} {reason}</title>
+<style>
+  body {{ font-family: sans-serif; margin: 3em auto; max-width: 40em; color: #333; }}
+  h1 {{ font-size: 1.6em; color: #b03030; }}
+  .url {{ font-family: monospace; word-break: break-all; background: #f4f4f4; padding: 0.5em; }}
+  .detail {{ font-size: 0.85em; color: #777; word-break: break-word; }}
+  a.retry {{ display: inline-block; margin-top: 1em; padding: 0.4em 1em; border: 1px solid #888; border-radius: 4px; text-decoration: none; color: #333; }}
+</style>
+</head>
+<body>
+<h1>{code} {reason}</h1>
+<p>{kind}</p>
+<p class="url">{url}</p>
+<p class="detail">{detail}</p>
+<a class="retry" href="{url}">Retry</a>
+</body>
+</html>
+"#,
+        code = status.as_u16(),
+        reason = html_escape(reason),
+        kind = html_escape(kind),
+        detail = html_escape(detail),
+    )
+}
+
+pub fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use color_eyre::eyre::eyre;
+
+    fn exit_code_of(error: Report) -> i32 {
+        CliError::from_report(&error).exit_code()
+    }
+
+    #[test]
+    fn other_failures_exit_with_1() {
+        assert_eq!(exit_code_of(eyre!("Something went wrong")), 1);
+        // A message alone doesn't make a failure one of the typed kinds
+        assert_eq!(exit_code_of(eyre!("Insufficient gas to publish")), 1);
+        assert_eq!(
+            exit_code_of(Report::new(ProtocolError::Deserialisation(String::from(
+                "bad"
+            )))),
+            1
+        );
+    }
+
+    #[test]
+    fn connection_failures_exit_with_3() {
+        let error = Report::new(CliError::Connection(String::from("no peers")));
+        assert_eq!(exit_code_of(error), 3);
+        let error = Report::new(ProtocolError::NetworkUnavailable(String::from("timeout")));
+        assert_eq!(exit_code_of(error), 3);
+    }
+
+    #[test]
+    fn insufficient_funds_exit_with_4() {
+        let error = Report::new(CliError::InsufficientFunds(String::from("no tokens")));
+        assert_eq!(exit_code_of(error.wrap_err("Failed to publish files")), 4);
+    }
+
+    #[test]
+    fn bad_addresses_exit_with_5() {
+        let error = Report::new(ProtocolError::BadAddress(String::from("not hex")));
+        assert_eq!(exit_code_of(error), 5);
+        let error = Report::new(ProtocolError::BadRequest(String::from("bad URL")));
+        assert_eq!(exit_code_of(error), 5);
+    }
+
+    #[test]
+    fn missing_content_exits_with_6() {
+        let error = Report::new(ProtocolError::NotFound(String::from("/missing.html")));
+        assert_eq!(exit_code_of(error), 6);
+        let error = Report::new(NotCached(String::from("Content")));
+        assert_eq!(exit_code_of(error.wrap_err("Offline")), 6);
+    }
+
+    #[test]
+    fn partial_uploads_exit_with_7() {
+        let error = Report::new(CliError::PartialUpload(String::from("3 chunks failed")));
+        assert_eq!(exit_code_of(error), 7);
+    }
+
+    #[test]
+    fn outer_message_is_kept() {
+        let error = Report::new(CliError::Connection(String::from("no peers")))
+            .wrap_err("Failed to connect to Autonomi Network");
+        let cli_error = CliError::from_report(&error);
+        assert_eq!(cli_error.kind(), "connection");
+        assert_eq!(
+            cli_error.to_string(),
+            "Failed to connect to Autonomi Network"
+        );
+    }
+}
@@ -0,0 +1,512 @@
+/*
+Copyright (c) 2024-2025 Mark Hughes
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+//! HTTP request and response handling shared by the awe protocol handlers
+
+use http::{header, status::StatusCode, Request};
+
+use autonomi::chunk::DataMapChunk;
+use autonomi::client::data::DataAddress;
+
+use crate::awe_cache::ContentCache;
+
+const RANGE_UNIT_PREFIX: &str = "bytes=";
+
+pub const URL_PARAM_CONTENT_TYPE: &str = "type";
+
+/// Header on responses served from the cache while offline
+pub const OFFLINE_HEADER: &str = "x-awe-offline";
+pub const OFFLINE_HEADER_VALUE: &str = "cached";
+const DEFAULT_CONTENT_TYPE: &str = "application/octet-stream";
+const TEXT_CONTENT_TYPE: &str = "text/plain; charset=utf-8";
+const HTML_CONTENT_TYPE: &str = "text/html; charset=utf-8";
+const SNIFF_LENGTH: usize = 512;
+
+/// Decide the Content-Type of a response. The single policy used by all handlers.
+///
+/// Uses the first available of:
+///   known_type (from the Tree, or a ?type= URL parameter)
+///   the extension of resource_path
+///   the magic bytes at the start of the content
+pub fn content_type_for(
+    known_type: Option<&str>,
+    resource_path: Option<&str>,
+    content: &[u8],
+) -> String {
+    if let Some(known_type) = known_type.filter(|t| is_valid_content_type(t)) {
+        return known_type.to_string();
+    }
+
+    if let Some(resource_path) = resource_path {
+        if let Some(guess) = mime_guess::from_path(resource_path).first_raw() {
+            return guess.to_string();
+        }
+    }
+
+    sniff_content_type(content)
+}
+
+/// Guess a content type from the first bytes of content
+pub fn sniff_content_type(content: &[u8]) -> String {
+    if let Some(kind) = infer::get(content) {
+        return kind.mime_type().to_string();
+    }
+
+    let start = &content[..content.len().min(SNIFF_LENGTH)];
+    let Ok(text) = std::str::from_utf8(start) else {
+        return DEFAULT_CONTENT_TYPE.to_string();
+    };
+
+    let text = text.trim_start().to_ascii_lowercase();
+    if text.starts_with("<!doctype html") || text.starts_with("<html") || text.starts_with("<head")
+    {
+        HTML_CONTENT_TYPE.to_string()
+    } else if text.starts_with("<svg") || (text.starts_with("<?xml") && text.contains("<svg")) {
+        String::from("image/svg+xml")
+    } else {
+        TEXT_CONTENT_TYPE.to_string()
+    }
+}
+
+/// True if content_type looks like 'type/subtype' and is usable as a header value
+pub fn is_valid_content_type(content_type: &str) -> bool {
+    content_type.contains('/') && header::HeaderValue::from_str(content_type).is_ok()
+}
+
+/// An encoding in which a file may be stored alongside the original, as '<path>.br' or '<path>.gz'
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ContentEncoding {
+    Brotli,
+    Gzip,
+}
+
+impl ContentEncoding {
+    /// In order of preference
+    pub const ALL: [ContentEncoding; 2] = [ContentEncoding::Brotli, ContentEncoding::Gzip];
+
+    /// The value used in Accept-Encoding and Content-Encoding headers
+    pub fn header_value(&self) -> &'static str {
+        match self {
+            ContentEncoding::Brotli => "br",
+            ContentEncoding::Gzip => "gzip",
+        }
+    }
+
+    /// The suffix added to the path of the original file
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ContentEncoding::Brotli => ".br",
+            ContentEncoding::Gzip => ".gz",
+        }
+    }
+}
+
+/// The encodings the request says it accepts, in order of preference
+///
+/// Encodings given 'q=0' are refused, and '*' accepts any not otherwise mentioned.
+pub fn accepted_encodings(req: &Request<Vec<u8>>) -> Vec<ContentEncoding> {
+    let Some(accept_encoding) = req
+        .headers()
+        .get(header::ACCEPT_ENCODING)
+        .and_then(|value| value.to_str().ok())
+    else {
+        return Vec::new();
+    };
+
+    let mut accepted = Vec::<ContentEncoding>::new();
+    let mut refused = Vec::<ContentEncoding>::new();
+    let mut accept_any = false;
+    for item in accept_encoding.split(',') {
+        let mut fields = item.split(';');
+        let coding = fields.next().unwrap_or("").trim().to_ascii_lowercase();
+        let refuse = fields.any(|param| {
+            let param = param.trim().replace(' ', "");
+            param
+                .strip_prefix("q=")
+                .and_then(|q| q.parse::<f32>().ok())
+                .is_some_and(|q| q <= 0.0)
+        });
+
+        if coding == "*" {
+            accept_any = !refuse;
+            continue;
+        }
+        let Some(encoding) = ContentEncoding::ALL
+            .into_iter()
+            .find(|encoding| encoding.header_value() == coding)
+        else {
+            continue;
+        };
+        if refuse {
+            refused.push(encoding);
+        } else {
+            accepted.push(encoding);
+        }
+    }
+
+    ContentEncoding::ALL
+        .into_iter()
+        .filter(|encoding| {
+            !refused.contains(encoding) && (accept_any || accepted.contains(encoding))
+        })
+        .collect()
+}
+
+/// How long the webview may reuse a response without asking again
+#[derive(Clone, Copy, Debug)]
+pub enum CacheControl {
+    /// Content-addressed, so it can never change
+    Immutable,
+    /// May change (e.g. the latest version of a website), so reuse for this many seconds
+    ShortLived(u64),
+}
+
+impl CacheControl {
+    pub fn header_value(&self) -> String {
+        match self {
+            CacheControl::Immutable => String::from("public, max-age=31536000, immutable"),
+            CacheControl::ShortLived(seconds) => format!("public, max-age={seconds}"),
+        }
+    }
+}
+
+/// Return a strong ETag for the content at a DataMapChunk or DataAddress
+///
+/// Because content on Autonomi is addressed by its hash the address identifies the
+/// bytes exactly, so it serves as a strong validator.
+pub fn etag_for(
+    datamap_chunk: &Option<DataMapChunk>,
+    data_address: &Option<DataAddress>,
+) -> Option<String> {
+    ContentCache::key_for(datamap_chunk, data_address).map(|key| format!("\"{key}\""))
+}
+
+/// True if the request has an If-None-Match header which matches etag
+pub fn request_matches_etag(req: &Request<Vec<u8>>, etag: &str) -> bool {
+    let Some(if_none_match) = req
+        .headers()
+        .get(header::IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+    else {
+        return false;
+    };
+
+    if_none_match
+        .split(',')
+        .map(|tag| tag.trim().trim_start_matches("W/"))
+        .any(|tag| tag == "*" || tag == etag)
+}
+
+/// Response telling the webview to use its cached copy
+pub fn not_modified_response(etag: &str, cache_control: CacheControl) -> http::Response<Vec<u8>> {
+    http::Response::builder()
+        .status(StatusCode::NOT_MODIFIED)
+        .header(header::ETAG, etag)
+        .header(header::CACHE_CONTROL, cache_control.header_value())
+        .body(Vec::new())
+        .unwrap()
+}
+
+/// The part of a body selected by a Range header
+#[derive(Debug, PartialEq)]
+pub enum ByteRange {
+    /// No usable Range header, so the whole body
+    Full,
+    /// Inclusive start and end positions
+    Partial(u64, u64),
+    /// A range that lies outside the body
+    Unsatisfiable,
+}
+
+/// Parse the value of a Range header for a body of content_length bytes
+///
+/// Only a single range is supported. Multiple ranges and headers that can't be
+/// parsed select the full body, which is allowed by RFC 9110.
+pub fn parse_range_header(value: &str, content_length: u64) -> ByteRange {
+    let Some(range) = value.trim().strip_prefix(RANGE_UNIT_PREFIX) else {
+        return ByteRange::Full;
+    };
+    if range.contains(',') {
+        return ByteRange::Full;
+    }
+    let Some((start, end)) = range.trim().split_once('-') else {
+        return ByteRange::Full;
+    };
+
+    if start.is_empty() {
+        // Suffix range: the last N bytes
+        let Ok(suffix_length) = end.parse::<u64>() else {
+            return ByteRange::Full;
+        };
+        if suffix_length == 0 || content_length == 0 {
+            return ByteRange::Unsatisfiable;
+        }
+        let start = content_length.saturating_sub(suffix_length);
+        return ByteRange::Partial(start, content_length - 1);
+    }
+
+    let Ok(start) = start.parse::<u64>() else {
+        return ByteRange::Full;
+    };
+    if start >= content_length {
+        return ByteRange::Unsatisfiable;
+    }
+
+    let end = if end.is_empty() {
+        content_length - 1
+    } else {
+        match end.parse::<u64>() {
+            Ok(end) if end >= start => end.min(content_length - 1),
+            _ => return ByteRange::Full,
+        }
+    };
+
+    ByteRange::Partial(start, end)
+}
+
+/// Apply any Range header in the request to a complete (200 OK) response
+///
+/// Returns 206 Partial Content with the selected bytes, 416 Range Not Satisfiable,
+/// or the response unchanged. Every 200 response advertises 'Accept-Ranges: bytes'
+/// so that media elements know they can seek.
+pub fn apply_range_request(
+    req: &Request<Vec<u8>>,
+    response: http::Response<Vec<u8>>,
+) -> http::Response<Vec<u8>> {
+    if response.status() != StatusCode::OK {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    parts.headers.insert(
+        header::ACCEPT_RANGES,
+        header::HeaderValue::from_static("bytes"),
+    );
+
+    let content_length = body.len() as u64;
+    let byte_range = match req
+        .headers()
+        .get(header::RANGE)
+        .and_then(|value| value.to_str().ok())
+    {
+        Some(value) => parse_range_header(value, content_length),
+        None => ByteRange::Full,
+    };
+
+    match byte_range {
+        ByteRange::Full => http::Response::from_parts(parts, body),
+        ByteRange::Partial(start, end) => {
+            println!("DEBUG serving bytes {start}-{end} of {content_length}");
+            parts.status = StatusCode::PARTIAL_CONTENT;
+            let content_range = format!("bytes {start}-{end}/{content_length}");
+            if let Ok(content_range) = header::HeaderValue::from_str(&content_range) {
+                parts.headers.insert(header::CONTENT_RANGE, content_range);
+            }
+            let body = body[start as usize..=end as usize].to_vec();
+            http::Response::from_parts(parts, body)
+        }
+        ByteRange::Unsatisfiable => range_not_satisfiable_response(content_length),
+    }
+}
+
+/// Choose the bytes of a streamed body of content_length bytes to send in reply to req
+///
+/// A partial response holds at most window bytes, so a Range request for more is answered
+/// with the first window bytes of what was asked for, and a request with no usable Range
+/// header with the first window bytes of the body. Media elements and downloaders then
+/// ask for the rest with further Range requests.
+pub fn streamed_range(req: &Request<Vec<u8>>, content_length: u64, window: u64) -> ByteRange {
+    let byte_range = match req
+        .headers()
+        .get(header::RANGE)
+        .and_then(|value| value.to_str().ok())
+    {
+        Some(value) => parse_range_header(value, content_length),
+        None => ByteRange::Full,
+    };
+
+    match byte_range {
+        ByteRange::Full if content_length == 0 => ByteRange::Full,
+        ByteRange::Full => ByteRange::Partial(0, content_length.min(window) - 1),
+        ByteRange::Partial(start, end) => ByteRange::Partial(start, end.min(start + window - 1)),
+        ByteRange::Unsatisfiable => ByteRange::Unsatisfiable,
+    }
+}
+
+/// Response to a Range header which selects nothing from a body of content_length bytes
+pub fn range_not_satisfiable_response(content_length: u64) -> http::Response<Vec<u8>> {
+    let content_range = format!("bytes */{content_length}");
+    http::Response::builder()
+        .status(StatusCode::RANGE_NOT_SATISFIABLE)
+        .header(header::CONTENT_RANGE, content_range)
+        .body(Vec::new())
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request_with_range(range: &str) -> Request<Vec<u8>> {
+        Request::builder()
+            .uri("awf://example/file")
+            .header(header::RANGE, range)
+            .body(Vec::new())
+            .unwrap()
+    }
+
+    fn ok_response(body: &[u8]) -> http::Response<Vec<u8>> {
+        http::Response::builder()
+            .status(StatusCode::OK)
+            .body(body.to_vec())
+            .unwrap()
+    }
+
+    #[test]
+    fn open_ended_range_is_the_rest() {
+        assert_eq!(
+            parse_range_header("bytes=0-", 1000),
+            ByteRange::Partial(0, 999)
+        );
+        assert_eq!(
+            parse_range_header("bytes=990-", 1000),
+            ByteRange::Partial(990, 999)
+        );
+    }
+
+    #[test]
+    fn suffix_range_is_the_last_bytes() {
+        assert_eq!(
+            parse_range_header("bytes=-500", 1000),
+            ByteRange::Partial(500, 999)
+        );
+        assert_eq!(
+            parse_range_header("bytes=-5000", 1000),
+            ByteRange::Partial(0, 999)
+        );
+        assert_eq!(
+            parse_range_header("bytes=-0", 1000),
+            ByteRange::Unsatisfiable
+        );
+    }
+
+    #[test]
+    fn end_past_eof_is_clamped() {
+        assert_eq!(
+            parse_range_header("bytes=100-5000", 1000),
+            ByteRange::Partial(100, 999)
+        );
+    }
+
+    #[test]
+    fn start_past_eof_is_unsatisfiable() {
+        assert_eq!(
+            parse_range_header("bytes=1000-", 1000),
+            ByteRange::Unsatisfiable
+        );
+        assert_eq!(
+            parse_range_header("bytes=2000-3000", 1000),
+            ByteRange::Unsatisfiable
+        );
+    }
+
+    #[test]
+    fn start_after_end_selects_everything() {
+        assert_eq!(parse_range_header("bytes=500-100", 1000), ByteRange::Full);
+    }
+
+    #[test]
+    fn multiple_ranges_select_everything() {
+        assert_eq!(
+            parse_range_header("bytes=0-10,20-30", 1000),
+            ByteRange::Full
+        );
+    }
+
+    #[test]
+    fn malformed_ranges_select_everything() {
+        for value in [
+            "",
+            "bytes=",
+            "bytes=abc-",
+            "bytes=0-xyz",
+            "bytes=10",
+            "items=0-10",
+        ] {
+            assert_eq!(parse_range_header(value, 1000), ByteRange::Full, "{value}");
+        }
+    }
+
+    #[test]
+    fn nothing_can_be_selected_from_empty_content() {
+        assert_eq!(parse_range_header("bytes=0-", 0), ByteRange::Unsatisfiable);
+        assert_eq!(parse_range_header("bytes=-10", 0), ByteRange::Unsatisfiable);
+    }
+
+    #[test]
+    fn partial_response_has_the_selected_bytes() {
+        let response =
+            apply_range_request(&request_with_range("bytes=2-4"), ok_response(b"0123456789"));
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(response.headers()[header::CONTENT_RANGE], "bytes 2-4/10");
+        assert_eq!(response.body(), b"234");
+    }
+
+    #[test]
+    fn unsatisfiable_ranges_are_answered_with_416() {
+        for (range, body) in [
+            ("bytes=10-", &b"0123456789"[..]),
+            ("bytes=-0", &b"0123456789"[..]),
+            ("bytes=0-", &b""[..]),
+        ] {
+            let response = apply_range_request(&request_with_range(range), ok_response(body));
+            assert_eq!(
+                response.status(),
+                StatusCode::RANGE_NOT_SATISFIABLE,
+                "{range}"
+            );
+            assert_eq!(
+                response.headers()[header::CONTENT_RANGE],
+                format!("bytes */{}", body.len()).as_str()
+            );
+        }
+    }
+
+    #[test]
+    fn streamed_ranges_are_limited_to_a_window() {
+        let no_range = Request::builder()
+            .uri("awf://example/file")
+            .body(Vec::new())
+            .unwrap();
+        assert_eq!(
+            streamed_range(&no_range, 1000, 100),
+            ByteRange::Partial(0, 99)
+        );
+        assert_eq!(
+            streamed_range(&request_with_range("bytes=200-"), 1000, 100),
+            ByteRange::Partial(200, 299)
+        );
+        assert_eq!(
+            streamed_range(&request_with_range("bytes=950-"), 1000, 100),
+            ByteRange::Partial(950, 999)
+        );
+        assert_eq!(
+            streamed_range(&request_with_range("bytes=0-10,20-30"), 1000, 100),
+            ByteRange::Partial(0, 99)
+        );
+    }
+}
@@ -0,0 +1,243 @@
+/*
+Copyright (c) 2024-2025 Mark Hughes
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+//! Directory listings for awm:// and awv:// paths which have no index.html
+
+use std::collections::{BTreeMap, HashMap};
+
+use http::{header, status::StatusCode};
+
+use dweb::files::directory::Tree;
+
+use crate::awe_errors::html_escape;
+use crate::awe_url::percent_encode_path;
+
+pub const URL_PARAM_LIST: &str = "list";
+
+/// When to show a directory listing instead of an error
+pub struct DirectoryListing {
+    /// List any directory which has no index, not just when asked with ?list=1
+    when_no_index: bool,
+}
+
+impl DirectoryListing {
+    /// Use the --directory-listing setting from the CLI
+    pub fn new_default() -> DirectoryListing {
+        use crate::cli_options::Opt;
+        use clap::Parser;
+        let opt = Opt::parse();
+
+        DirectoryListing {
+            when_no_index: opt.directory_listing,
+        }
+    }
+
+    /// True if a failed lookup with these URL parameters may be shown as a listing
+    pub fn enabled_for(&self, url_params: &HashMap<String, String>) -> bool {
+        self.when_no_index || listing_requested(url_params)
+    }
+}
+
+/// True if the URL asks for a listing (e.g. ?list=1)
+pub fn listing_requested(url_params: &HashMap<String, String>) -> bool {
+    match url_params.get(URL_PARAM_LIST) {
+        Some(value) => value != "0" && value != "false",
+        None => false,
+    }
+}
+
+/// A file or subdirectory shown in a directory listing
+#[derive(Debug)]
+pub struct ListingEntry {
+    pub name: String,
+    pub is_directory: bool,
+    /// Size in bytes (for a directory, the total of the files it contains)
+    pub size: u64,
+    /// Modification time in seconds since the Unix epoch (for a directory, the most recent)
+    pub modified: u64,
+}
+
+/// Return the entries of directory in tree, or None if there is no such directory
+///
+/// Subdirectories are listed before files and each group is sorted by name.
+pub fn list_directory(tree: &Tree, directory: &str) -> Option<Vec<ListingEntry>> {
+    let directory = directory.trim_matches('/');
+    let prefix = if directory.is_empty() {
+        String::from("")
+    } else {
+        format!("{directory}/")
+    };
+
+    let mut directories = BTreeMap::<String, ListingEntry>::new();
+    let mut files = BTreeMap::<String, ListingEntry>::new();
+    for (path, _datamap_chunk, metadata) in tree.archive.iter() {
+        let path = path.to_string_lossy().replace('\\', "/");
+        let Some(remainder) = path.trim_start_matches('/').strip_prefix(&prefix) else {
+            continue;
+        };
+
+        match remainder.split_once('/') {
+            Some((subdirectory, _)) => {
+                let entry = directories
+                    .entry(subdirectory.to_string())
+                    .or_insert_with(|| ListingEntry {
+                        name: subdirectory.to_string(),
+                        is_directory: true,
+                        size: 0,
+                        modified: 0,
+                    });
+                entry.size += metadata.size;
+                entry.modified = entry.modified.max(metadata.modified);
+            }
+            None => {
+                files.insert(
+                    remainder.to_string(),
+                    ListingEntry {
+                        name: remainder.to_string(),
+                        is_directory: false,
+                        size: metadata.size,
+                        modified: metadata.modified,
+                    },
+                );
+            }
+        }
+    }
+
+    if directories.is_empty() && files.is_empty() {
+        return None;
+    }
+
+    Some(
+        directories
+            .into_values()
+            .chain(files.into_values())
+            .collect(),
+    )
+}
+
+/// Return an HTML page listing entries of directory, served at url
+///
+/// Links are relative to the root of the URL so work for both awm:// and awv://.
+/// If keep_list_param is true, links to subdirectories also ask for a listing.
+pub fn listing_response(
+    url: &str,
+    directory: &str,
+    entries: &[ListingEntry],
+    keep_list_param: bool,
+) -> http::Response<Vec<u8>> {
+    let directory = directory.trim_matches('/');
+    let base = if directory.is_empty() {
+        String::from("/")
+    } else {
+        format!("/{directory}/")
+    };
+    let list_param = if keep_list_param {
+        format!("?{URL_PARAM_LIST}=1")
+    } else {
+        String::from("")
+    };
+
+    let mut rows = String::new();
+    if !directory.is_empty() {
+        let parent = match directory.rsplit_once('/') {
+            Some((parent, _)) => format!("/{parent}/"),
+            None => String::from("/"),
+        };
+        rows.push_str(&format!(
+            "<tr><td><a href=\"{}{list_param}\">../</a></td><td></td><td></td></tr>\n",
+            html_escape(&percent_encode_path(&parent))
+        ));
+    }
+
+    // Names are shown as they are, but are percent-encoded in links so that a name
+    // containing '?', '#' or '%' still links to the right file
+    let base_href = percent_encode_path(&base);
+    for entry in entries {
+        let name_href = percent_encode_path(&entry.name);
+        let (name, href) = if entry.is_directory {
+            (
+                format!("{}/", entry.name),
+                format!("{base_href}{name_href}/{list_param}"),
+            )
+        } else {
+            (entry.name.clone(), format!("{base_href}{name_href}"))
+        };
+        rows.push_str(&format!(
+            "<tr><td><a href=\"{}\">{}</a></td><td class=\"size\">{}</td><td>{}</td></tr>\n",
+            html_escape(&href),
+            html_escape(&name),
+            format_size(entry.size),
+            format_modified(entry.modified)
+        ));
+    }
+
+    let page = format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>Index of {base}</title>
+<style>
+  body {{ font-family: sans-serif; margin: 2em; color: #333; }}
+  h1 {{ font-size: 1.4em; }}
+  .url {{ font-family: monospace; word-break: break-all; color: #777; }}
+  table {{ border-collapse: collapse; }}
+  th, td {{ text-align: left; padding: 0.2em 1.5em 0.2em 0; }}
+  td.size {{ text-align: right; }}
+</style>
+</head>
+<body>
+<h1>Index of {base}</h1>
+<p class="url">{url}</p>
+<table>
+<tr><th>Name</th><th>Size</th><th>Modified</th></tr>
+{rows}</table>
+</body>
+</html>
+"#,
+        base = html_escape(&base),
+        url = html_escape(url),
+    );
+
+    http::Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "text/html; charset=utf-8")
+        .header(header::CACHE_CONTROL, "no-cache")
+        .body(page.into_bytes())
+        .unwrap()
+}
+
+fn format_size(size: u64) -> String {
+    const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+    if size < 1024 {
+        return format!("{size} B");
+    }
+    let mut size = size as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{size:.1} {}", UNITS[unit])
+}
+
+fn format_modified(modified: u64) -> String {
+    match chrono::DateTime::from_timestamp(modified as i64, 0) {
+        Some(time) if modified > 0 => time.format("%Y-%m-%d %H:%M").to_string(),
+        _ => String::from(""),
+    }
+}
@@ -0,0 +1,175 @@
+/*
+Copyright (c) 2024-2025 Mark Hughes
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+
+use color_eyre::eyre::{eyre, Result};
+
+use dweb::client::DwebClient;
+use dweb::history::HistoryAddress;
+
+use crate::awe_cache::{get_content_cached, ContentCache};
+use crate::awe_client::awe_str_to_history_address;
+use crate::awe_protocols::AWE_PROTOCOL_FILE;
+use crate::awe_url::AweUrl;
+
+const NAMES_FILE: &str = "awe/names.toml";
+const NAMES_FILE_HEADER: &str = "# awe web names, used by awe://<NAME> URLs\n\
+# Each line is: <NAME> = \"<HISTORY-ADDRESS>\"\n\n";
+
+/// A registry of web names for use in awe://<NAME> URLs
+///
+/// Names are looked up first in a local petname file which the user can edit by hand
+/// or using the name-add and name-remove subcommands. If not found there, an optional
+/// shared list is checked. This can be a file path or an awf:// URL, so that a list
+/// of names can be published to Autonomi and shared by a team.
+///
+/// Both use the same TOML format, mapping each name to a HISTORY-ADDRESS.
+pub struct NameRegistry {
+    local_path: PathBuf,
+    shared_list: Option<String>,
+}
+
+impl NameRegistry {
+    /// Open the registry in the platform data directory with any shared list given on the CLI
+    pub fn open_default() -> Result<NameRegistry> {
+        use crate::cli_options::Opt;
+        use clap::Parser;
+        let opt = Opt::parse();
+
+        let Some(data_dir) = dirs_next::data_dir() else {
+            return Err(eyre!("Unable to find the platform data directory"));
+        };
+
+        Ok(NameRegistry {
+            local_path: data_dir.join(NAMES_FILE),
+            shared_list: opt.shared_names,
+        })
+    }
+
+    pub fn local_path(&self) -> &PathBuf {
+        &self.local_path
+    }
+
+    /// Return the names in the local petname file
+    pub fn local_names(&self) -> Result<BTreeMap<String, String>> {
+        if !self.local_path.exists() {
+            return Ok(BTreeMap::new());
+        }
+        let text = fs::read_to_string(&self.local_path)?;
+        parse_names(&text)
+    }
+
+    /// Add or replace a name in the local petname file
+    pub fn add(&self, name: &str, history_address: HistoryAddress) -> Result<()> {
+        validate_name(name)?;
+        let mut names = self.local_names()?;
+        names.insert(name.to_string(), history_address.to_hex());
+        self.save_local_names(&names)
+    }
+
+    /// Remove a name from the local petname file. Returns false if it was not present.
+    pub fn remove(&self, name: &str) -> Result<bool> {
+        let mut names = self.local_names()?;
+        if names.remove(name).is_none() {
+            return Ok(false);
+        }
+        self.save_local_names(&names)?;
+        Ok(true)
+    }
+
+    fn save_local_names(&self, names: &BTreeMap<String, String>) -> Result<()> {
+        if let Some(parent) = self.local_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let text = format!("{NAMES_FILE_HEADER}{}", toml::to_string(names)?);
+        fs::write(&self.local_path, text)?;
+        Ok(())
+    }
+
+    /// Return the names in the shared list, fetching it from Autonomi if it is an awf:// URL
+    ///
+    /// With no client (when offline) an awf:// list is only available if cached.
+    pub async fn shared_names(
+        &self,
+        client: Option<&DwebClient>,
+        content_cache: Option<&ContentCache>,
+    ) -> Result<BTreeMap<String, String>> {
+        let Some(shared_list) = &self.shared_list else {
+            return Ok(BTreeMap::new());
+        };
+
+        let text = if shared_list.starts_with(AWE_PROTOCOL_FILE) {
+            let data_address = AweUrl::parse(shared_list)?.data_address()?;
+            let content =
+                get_content_cached(client, content_cache, None, Some(data_address)).await?;
+            String::from_utf8(content.to_vec())?
+        } else {
+            fs::read_to_string(shared_list)?
+        };
+        parse_names(&text)
+    }
+
+    /// Resolve name to a HistoryAddress using the local file and then the shared list
+    pub async fn resolve(
+        &self,
+        client: Option<&DwebClient>,
+        content_cache: Option<&ContentCache>,
+        name: &str,
+    ) -> Result<HistoryAddress> {
+        let name = name.to_lowercase();
+        let address = match self.local_names()?.get(&name) {
+            Some(address) => address.clone(),
+            None => match self.shared_names(client, content_cache).await?.get(&name) {
+                Some(address) => address.clone(),
+                None => return Err(eyre!("Unknown web name '{name}'")),
+            },
+        };
+
+        println!("DEBUG resolved web name '{name}' to {address}");
+        awe_str_to_history_address(&address)
+    }
+}
+
+fn parse_names(text: &str) -> Result<BTreeMap<String, String>> {
+    let names: BTreeMap<String, String> = toml::from_str(text)?;
+    Ok(names
+        .into_iter()
+        .map(|(name, address)| (name.to_lowercase(), address))
+        .collect())
+}
+
+/// A web name is used as the host of an awe:// URL so is limited to lowercase
+/// letters, digits and hyphens, and may not start or end with a hyphen
+pub fn validate_name(name: &str) -> Result<()> {
+    let valid = !name.is_empty()
+        && !name.starts_with('-')
+        && !name.ends_with('-')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+
+    if valid {
+        Ok(())
+    } else {
+        Err(eyre!(
+            "Invalid name '{name}': use lowercase letters, digits and '-' (not at the start or end)"
+        ))
+    }
+}
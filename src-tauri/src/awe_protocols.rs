@@ -17,131 +17,67 @@ along with this program. If not, see <https://www.gnu.org/licenses/>.
 use std::sync::LazyLock;
 use std::sync::Mutex;
 
-use dweb::files::directory::get_content_using_hex;
+use bytes::Bytes;
+use color_eyre::eyre::Report;
+
 use http::{header, status::StatusCode, Request};
-use mime_guess;
+use tauri::{AppHandle, Manager, State};
 
 use autonomi::chunk::DataMapChunk;
 use autonomi::client::data::DataAddress;
 use autonomi::client::GetError;
 
 use dweb::client::DwebClient;
-use dweb::files::archive::ARCHIVE_PATH_SEPARATOR;
-use dweb::files::directory::{datamap_and_address_from_hex, get_content, Tree};
-use dweb::helpers::convert::{awe_str_to_data_address, awe_str_to_history_address};
-use dweb::history::{History, HistoryAddress};
-
-use crate::awe_client::connect_to_autonomi;
+use dweb::files::directory::{datamap_and_address_from_hex, Tree};
+use dweb::history::HistoryAddress;
+
+use crate::awe_cache::{get_content_or_stream_cached, ContentCache, FetchedContent, HistoryCache};
+use crate::awe_client::{awe_str_to_history_address, SharedClient};
+use crate::awe_diff::{diff_versions, VersionDiff};
+use crate::awe_errors::ProtocolError;
+use crate::awe_http::{
+    accepted_encodings, apply_range_request, content_type_for, etag_for, not_modified_response,
+    range_not_satisfiable_response, request_matches_etag, streamed_range, ByteRange, CacheControl,
+    ContentEncoding, OFFLINE_HEADER, OFFLINE_HEADER_VALUE, URL_PARAM_CONTENT_TYPE,
+};
+use crate::awe_listing::{list_directory, listing_requested, listing_response, DirectoryListing};
+use crate::awe_names::NameRegistry;
+use crate::awe_session::{BrowsingSessions, OfflineStatus};
+use crate::awe_site::{lookup_with_rules, SiteConfigs, SiteLookup};
+use crate::awe_stream::STREAM_WINDOW;
+use crate::awe_timeline::{watch_for_newer_versions, VersionSummary, VersionTimelines};
+use crate::awe_url::{AweScheme, AweUrl, VersionPin};
 
 pub const AWE_PROTOCOL_HISTORY: &str = "awv://";
-#[allow(dead_code)]
 pub const AWE_PROTOCOL_DIRECTORY: &str = "awm://";
-#[allow(dead_code)]
 pub const AWE_PROTOCOL_FILE: &str = "awf://";
+pub const AWE_PROTOCOL_NAME: &str = "awe://";
 
 static STATIC_CLI_URL: LazyLock<Mutex<String>> =
     LazyLock::new(|| Mutex::<String>::new(String::from("")));
 
-// Set true by UI when it is about to navigate by setting the source URI
-static STATIC_NEXT_LOAD_IS_ADDRESS_BAR: LazyLock<Mutex<bool>> =
-    LazyLock::new(|| Mutex::<bool>::new(true));
-
-// Set true by UI after a page loads, so we can know the next load is not a page resource, but is a new page
-static STATIC_SAVE_NEXT_ADDRESS: LazyLock<Mutex<bool>> = LazyLock::new(|| Mutex::<bool>::new(true));
-
-// Address of the last site loaded via address bar or user clicking a link
-static STATIC_LAST_SITE_ADDRESS: LazyLock<Mutex<String>> =
-    LazyLock::new(|| Mutex::<String>::new(String::from("")));
-
-static STATIC_VERSION_REQUESTED: LazyLock<Mutex<u32>> = LazyLock::new(|| Mutex::<u32>::new(0));
-
-static STATIC_VERSION_LOADED: LazyLock<Mutex<u32>> = LazyLock::new(|| Mutex::<u32>::new(0));
-
-static STATIC_VERSION_MAX: LazyLock<Mutex<u32>> = LazyLock::new(|| Mutex::<u32>::new(0));
-
-pub fn get_next_load_is_address_bar() -> bool {
-    let flag = *STATIC_NEXT_LOAD_IS_ADDRESS_BAR.lock().unwrap();
-    println!("DEBUG get_next_load_is_address_bar() returning {}", flag);
-    flag
-}
-
-pub fn get_save_next_site_address() -> bool {
-    let flag = *STATIC_SAVE_NEXT_ADDRESS.lock().unwrap();
-    println!("DEBUG get_save_next_site_address() returning {}", flag);
-    flag
-}
-
-pub fn get_last_site_address() -> String {
-    let site_address = STATIC_LAST_SITE_ADDRESS.lock().unwrap();
-    println!("DEBUG get_last_site_address() returning {}", site_address);
-    site_address.clone()
-}
-
-pub fn get_version_requested() -> u32 {
-    let version = *STATIC_VERSION_REQUESTED.lock().unwrap();
-    println!("DEBUG get_version_requested() returning {}", version);
-    version
-}
-
-pub fn get_version_loaded() -> u32 {
-    let version = *STATIC_VERSION_LOADED.lock().unwrap();
-    println!("DEBUG get_version_loaded() returning {}", version);
-    version
-}
-
-pub fn get_version_max() -> u32 {
-    let version = *STATIC_VERSION_MAX.lock().unwrap();
-    println!("DEBUG get_version_max() returning {}", version);
-    version
-}
-
-pub fn set_next_load_is_address_bar(flag: bool) {
-    println!("DEBUG set_next_load_is_address_bar() set to {}", flag);
-    *STATIC_NEXT_LOAD_IS_ADDRESS_BAR.lock().unwrap() = flag;
-}
-
-pub fn set_save_next_site_address(flag: bool) {
-    println!("DEBUG set_save_next_site_address() set to {}", flag);
-    *STATIC_SAVE_NEXT_ADDRESS.lock().unwrap() = flag;
-}
-
-pub fn set_last_site_address(site_address: &String) {
-    if get_save_next_site_address() {
-        set_save_next_site_address(false);
-        println!("DEBUG set_last_site_address() set to {}", site_address);
-        *STATIC_LAST_SITE_ADDRESS.lock().unwrap() = site_address.clone();
-    }
-}
-
-pub fn set_version_requested(version: u32) {
-    println!("DEBUG set_version_requested() set to {}", version);
-    *STATIC_VERSION_REQUESTED.lock().unwrap() = version;
-}
-
-pub fn set_version_loaded(version: u32) {
-    println!("DEBUG set_version_loaded() set to {}", version);
-    *STATIC_VERSION_LOADED.lock().unwrap() = version;
-}
-
-// pub fn set_version_max(version: u32) {
-//     println!("DEBUG set_version_max() set to {}", version);
-//     *STATIC_VERSION_MAX.lock().unwrap() = version;
-// }
-
-const PROTOCOL_AWM: &str = "awm://";
-
 //// JavaScript interface
+//
+// Commands operate on the BrowsingSession of the webview which invoked them, which is
+// the same key the protocol handlers use for its requests
 
 #[tauri::command]
-fn on_set_save_next_site_address(flag: bool) {
+fn on_set_save_next_site_address(
+    webview: tauri::Webview,
+    sessions: State<BrowsingSessions>,
+    flag: bool,
+) {
     println!("DEBUG TT on_set_save_next_site_address() setting save_next_address: {flag}");
-    set_save_next_site_address(flag);
+    sessions.with_session(webview.label(), |session| {
+        session.set_save_next_site_address(flag)
+    });
 }
 
-// Obtain any URL provided to the CLI
+// Obtain the address of the site loaded in the window
 #[tauri::command]
-fn on_get_last_site_address() -> String {
-    let last_site_address = get_last_site_address();
+fn on_get_last_site_address(webview: tauri::Webview, sessions: State<BrowsingSessions>) -> String {
+    let last_site_address =
+        sessions.with_session(webview.label(), |session| session.site_address());
 
     println!("DEBUG TT tauri::cmd on_get_last_site_address() returning: {last_site_address}");
     last_site_address
@@ -163,101 +99,176 @@ fn on_start_get_cli_url() -> String {
     cli_url.to_string()
 }
 
-// Obtain website version requested on the CLI
+// Obtain website version requested (initially the one provided to the CLI)
 #[tauri::command]
-fn on_get_version_requested() -> usize {
-    let version = get_version_requested() as usize;
+fn on_get_version_requested(webview: tauri::Webview, sessions: State<BrowsingSessions>) -> usize {
+    let version =
+        sessions.with_session(webview.label(), |session| session.version_requested()) as usize;
     println!("DEBUG TT tauri::cmd on_get_version_requested() returning {version}");
-    version as usize
+    version
 }
 
-// Obtain loaded website version, or the one provided to the CLI
+// Obtain loaded website version
 #[tauri::command]
-fn on_get_version_loaded() -> usize {
-    let version = get_version_loaded() as usize;
+fn on_get_version_loaded(webview: tauri::Webview, sessions: State<BrowsingSessions>) -> usize {
+    let version =
+        sessions.with_session(webview.label(), |session| session.version_loaded()) as usize;
     println!("DEBUG TT tauri::cmd on_get_version_loaded() returning {version}");
-    version as usize
+    version
 }
 
 #[tauri::command]
-fn on_get_version_max() -> usize {
-    let version = get_version_max() as usize;
+fn on_get_version_max(webview: tauri::Webview, sessions: State<BrowsingSessions>) -> usize {
+    let version = sessions.with_session(webview.label(), |session| session.version_max()) as usize;
     println!("DEBUG TT tauri::cmd on_get_version_max() called from JS, returning {version}",);
     version
 }
 
+// Obtain a message for the banner shown when the page was served while offline,
+// or an empty string if it was loaded from the network
+#[tauri::command]
+fn on_get_offline_banner(webview: tauri::Webview, sessions: State<BrowsingSessions>) -> String {
+    let offline_status = sessions.with_session(webview.label(), |session| session.offline_status());
+    let banner = match offline_status {
+        None => String::from(""),
+        Some(OfflineStatus {
+            latest_resolved_at: Some(resolved_at),
+        }) => {
+            let age = resolved_at.elapsed().unwrap_or_default().as_secs();
+            format!(
+                "Offline: showing cached content. Latest version as of {} ago",
+                describe_age(age)
+            )
+        }
+        Some(_) => String::from("Offline: showing cached content"),
+    };
+    println!("DEBUG TT tauri::cmd on_get_offline_banner() returning: {banner}");
+    banner
+}
+
+fn describe_age(seconds: u64) -> String {
+    match seconds {
+        0..60 => format!("{seconds} seconds"),
+        60..3600 => format!("{} minutes", seconds / 60),
+        3600..86400 => format!("{} hours", seconds / 3600),
+        _ => format!("{} days", seconds / 86400),
+    }
+}
+
+// Move back or forward through the sites loaded in the webview, returning the address
+// to load, or None if there is nowhere to go
+#[tauri::command]
+fn on_navigate_back(webview: tauri::Webview, sessions: State<BrowsingSessions>) -> Option<String> {
+    let address = sessions.with_session(webview.label(), |session| session.navigate_back());
+    println!("DEBUG TT tauri::cmd on_navigate_back() returning: {address:?}");
+    address
+}
+
+#[tauri::command]
+fn on_navigate_forward(
+    webview: tauri::Webview,
+    sessions: State<BrowsingSessions>,
+) -> Option<String> {
+    let address = sessions.with_session(webview.label(), |session| session.navigate_forward());
+    println!("DEBUG TT tauri::cmd on_navigate_forward() returning: {address:?}");
+    address
+}
+
+// Obtain a summary of every version of the website at history_address, oldest first
+#[tauri::command]
+async fn on_get_version_timeline(
+    app: AppHandle,
+    history_address: String,
+) -> Result<Vec<VersionSummary>, String> {
+    println!("DEBUG TT tauri::cmd on_get_version_timeline({history_address})");
+    let history_address =
+        awe_str_to_history_address(&history_address).map_err(|e| e.to_string())?;
+    let client = client_or_offline(&app).await;
+    app.state::<VersionTimelines>()
+        .timeline(
+            client.as_ref(),
+            &app.state::<HistoryCache>(),
+            history_address,
+        )
+        .await
+        .map_err(|e| e.to_string())
+}
+
+// Compare two versions of the website at history_address, optionally with a
+// unified diff of each modified text file
+#[tauri::command]
+async fn on_get_version_diff(
+    app: AppHandle,
+    history_address: String,
+    from_version: u32,
+    to_version: u32,
+    text_diffs: bool,
+) -> Result<VersionDiff, String> {
+    println!(
+        "DEBUG TT tauri::cmd on_get_version_diff({history_address}, {from_version}, {to_version})"
+    );
+    let history_address =
+        awe_str_to_history_address(&history_address).map_err(|e| e.to_string())?;
+    let client = client_or_offline(&app).await;
+    let content_cache = app.state::<Option<ContentCache>>();
+    diff_versions(
+        client.as_ref(),
+        &app.state::<HistoryCache>(),
+        content_cache.as_ref(),
+        history_address,
+        from_version,
+        to_version,
+        text_diffs,
+    )
+    .await
+    .map_err(|e| e.to_string())
+}
+
 // Called by frontend before setting a new version URL parameter
 // Returns value truncated to the range 0..max version) or ZERO if no website loaded
 #[tauri::command]
-fn on_prep_to_load_from_address_bar(frontend_version: usize) -> usize {
-    let mut version = frontend_version as u32;
-
-    let max_version = get_version_max();
-    if max_version > 0 {
-        if version > max_version {
-            version = max_version;
+fn on_prep_to_load_from_address_bar(
+    webview: tauri::Webview,
+    sessions: State<BrowsingSessions>,
+    frontend_version: usize,
+) -> usize {
+    let version = sessions.with_session(webview.label(), |session| {
+        let mut version = frontend_version as u32;
+
+        let max_version = session.version_max();
+        if max_version > 0 {
+            if version > max_version {
+                version = max_version;
+            }
         }
-    }
-    set_next_load_is_address_bar(true);
+        session.set_next_load_is_address_bar(true);
+        session.set_version_requested(version);
+        version
+    });
 
     println!("DEBUG TT on_prep_to_load_from_address_bar({frontend_version}) returning version: {version}");
-    set_version_requested(version);
     version as usize
 }
 
-use color_eyre::eyre::{eyre, Report};
-use std::collections::HashMap;
-use url::Url;
 const PROTOCOL_END_STR: &str = "://";
 const URL_PARAM_VERSION: &str = "v";
 
-/// Parse an awe compatible URL into a tuple of:
-///   String protocol (e.g. "awv://")
-///   String XOR-ADDRESS or NRS host (including subdomains)
-///   String of the path part
-///   HashMap of query parameters to values
-fn parse_url_string(
-    url: String,
-) -> Result<(String, String, String, HashMap<String, String>), Report> {
-    println!("DEBUG parse_url_string({url}");
-
-    let protocol: String;
-    if let Some(colon_position) = url.find(PROTOCOL_END_STR) {
-        protocol = url[0..colon_position + PROTOCOL_END_STR.len()].to_string();
-        println!("DEBUG   protocol: {protocol}")
-    } else {
-        return Err(eyre!("Failed to parse URL (missing protocol): {}", url));
-    }
-
-    let parsed_url = Url::parse(url.as_str())?;
-
-    if !parsed_url.has_host() {
-        return Err(eyre!("Failed to parse URL (no host found): {}", url));
-    }
-    let query_params: HashMap<_, _> = parsed_url.query_pairs().into_owned().collect();
-
-    println!("DEBUG   host: {}", parsed_url.host().unwrap());
-    println!("DEBUG   path: {}", parsed_url.path().to_string());
-    println!("DEBUG   params: {:?}", query_params);
-
-    Ok((
-        protocol,
-        String::from(parsed_url.host_str().unwrap()),
-        parsed_url.path().to_string(),
-        query_params,
-    ))
+/// Parse the URL of a request, or return an error page
+fn parse_request_url(req: &Request<Vec<u8>>) -> Result<AweUrl, http::Response<Vec<u8>>> {
+    let url = req.uri().to_string();
+    println!("DEBUG parse_request_url({url})");
+    AweUrl::parse(&url).map_err(|e| {
+        ProtocolError::BadRequest(format!("Failed to parse URL: {e}")).to_response(&url)
+    })
 }
 
-// TODO See if I can instantiate a client and other startup things as static and share
-// TODO across handlers and requests. For now I make new client etc. for every request.
-// TODO So maybe make some objects static? (app_config / client / files_api)
+// The awe protocols are registered as asynchronous URI scheme protocols. Each request
+// is handled in its own task on the Tauri async runtime and answered using the
+// responder, so resources on a page load concurrently and a slow fetch doesn't
+// block the webview.
 //
-// TODO review technique used to call async within protocol handlers (discouraged here: https://stackoverflow.com/questions/66035290/how-do-i-await-a-future-inside-a-non-async-method-which-was-called-from-an-async)
-// TODO Tauri is supposed to support calling async inside protocol handler, but I didn't find a recommended method
-// TODO ASKED Tauri Discord: https://discord.com/channels/616186924390023171/1224053280565497896
-// TODO ASKED Stack Overflow:https://stackoverflow.com/questions/78255320/what-is-the-recommended-technique-for-calling-an-async-function-within-a-tauri-p
-// TODO Notes: Discord solution re Tauri 2.0 - thought I was using v2.0?
-// TODO Notes: Discord solution suggests I can use spawn after all, worth trying as this blocks for ages
+// The webview calls the registration closures in the order the requests are made, so
+// any navigation state which depends on that order is captured there, before spawning.
 //#[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn register_protocols(cli_url: Option<String>, cli_website_version: Option<u32>) {
     if cli_url.is_some() {
@@ -268,9 +279,6 @@ pub fn register_protocols(cli_url: Option<String>, cli_website_version: Option<u
         "DEBUG CLI specified --website-version {:?}",
         cli_website_version
     );
-    if cli_website_version.is_some() {
-        set_version_requested(cli_website_version.unwrap());
-    };
 
     tauri::Builder::default()
         // Rust functions available to JavaScript
@@ -283,7 +291,43 @@ pub fn register_protocols(cli_url: Option<String>, cli_website_version: Option<u
             on_get_version_loaded,
             on_get_version_requested,
             on_get_version_max,
+            on_get_offline_banner,
+            on_navigate_back,
+            on_navigate_forward,
+            on_get_version_timeline,
+            on_get_version_diff,
         ])
+        // One client shared by all protocol handlers, connected at startup
+        .manage(SharedClient::new_default())
+        // Immutable content fetched by the handlers is kept in an on-disk cache
+        .manage(ContentCache::open_default())
+        // Resolved versions of each History, so a page resolves its History once
+        .manage(HistoryCache::new_default())
+        // Navigation state for each window, starting with any version given on the CLI
+        .manage(BrowsingSessions::new(cli_website_version))
+        // Web names for awe:// URLs
+        .manage(NameRegistry::open_default().ok())
+        // Whether to list directories which have no index.html
+        .manage(DirectoryListing::new_default())
+        // Settings from the awe.toml of each site visited
+        .manage(SiteConfigs::new())
+        // Summaries of the versions of each site, for the version timeline
+        .manage(VersionTimelines::new())
+        .setup(|app| {
+            let shared_client = app.state::<SharedClient>().inner().clone();
+            if shared_client.is_offline() {
+                println!("Offline: browsing only what is in the local cache");
+                return Ok(());
+            }
+            tauri::async_runtime::spawn(async move {
+                if let Err(e) = shared_client.get().await {
+                    println!("Failed to connect to Autonomi Network: {e}");
+                }
+            });
+            // Tell each window when the site it shows has a newer version
+            tauri::async_runtime::spawn(watch_for_newer_versions(app.handle().clone()));
+            Ok(())
+        })
         .register_uri_scheme_protocol("test", |_app, req| {
             let url = req.uri();
             let content =
@@ -294,25 +338,58 @@ pub fn register_protocols(cli_url: Option<String>, cli_website_version: Option<u
                 .unwrap()
         })
         // Protocol for a file
-        .register_uri_scheme_protocol("awf", move |_app, req| {
-            tauri::async_runtime::block_on(async move { handle_protocol_awf(&req).await })
+        .register_asynchronous_uri_scheme_protocol("awf", move |ctx, req, responder| {
+            let app = ctx.app_handle().clone();
+            tauri::async_runtime::spawn(async move {
+                responder.respond(handle_protocol_awf(&app, &req).await);
+            });
         })
         // Protocol for a website (WebsiteMetadata)
-        .register_uri_scheme_protocol("awm", move |_app, req| {
-            tauri::async_runtime::block_on(async move { handle_protocol_awm(&req).await })
+        .register_asynchronous_uri_scheme_protocol("awm", move |ctx, req, responder| {
+            let app = ctx.app_handle().clone();
+            let webview_label = ctx.webview_label().to_string();
+            tauri::async_runtime::spawn(async move {
+                responder.respond(handle_protocol_awm(&app, &webview_label, &req).await);
+            });
         })
         // Protocol for a versioned website (WebsiteVersions)
-        .register_uri_scheme_protocol("awv", move |_app, req| {
-            let website_version = Some(get_version_requested());
-            tauri::async_runtime::block_on(async move {
-                handle_protocol_awv(&req, website_version).await
-            })
+        .register_asynchronous_uri_scheme_protocol("awv", move |ctx, req, responder| {
+            let app = ctx.app_handle().clone();
+            let webview_label = ctx.webview_label().to_string();
+            let (website_version, loading_new_page_via_address_bar) =
+                take_navigation_state(&app, &webview_label);
+            tauri::async_runtime::spawn(async move {
+                responder.respond(
+                    handle_protocol_awv(
+                        &app,
+                        &webview_label,
+                        &req,
+                        None,
+                        website_version,
+                        loading_new_page_via_address_bar,
+                    )
+                    .await,
+                );
+            });
         })
-        .register_uri_scheme_protocol("awe", move |_app, req| {
-            let website_version = Some(get_version_requested());
-            tauri::async_runtime::block_on(async move {
-                handle_protocol_awe(&req, website_version).await
-            })
+        // Protocol for a versioned website using a web name
+        .register_asynchronous_uri_scheme_protocol("awe", move |ctx, req, responder| {
+            let app = ctx.app_handle().clone();
+            let webview_label = ctx.webview_label().to_string();
+            let (website_version, loading_new_page_via_address_bar) =
+                take_navigation_state(&app, &webview_label);
+            tauri::async_runtime::spawn(async move {
+                responder.respond(
+                    handle_protocol_awe(
+                        &app,
+                        &webview_label,
+                        &req,
+                        website_version,
+                        loading_new_page_via_address_bar,
+                    )
+                    .await,
+                );
+            });
         })
         // The following macro may give the following 'cargo check' error which can be ignored.
         //      `frontendDist` configuration is set to `"../build"` but this path
@@ -321,66 +398,132 @@ pub fn register_protocols(cli_url: Option<String>, cli_website_version: Option<u
         .expect("error while running tauri application");
 }
 
-// TODO implement publishing via version (based on webname)
-// TODO Placeholder for awe:// webname protocol
+/// Return the version requested and whether this load is from the address bar
+/// for the session of webview_label. Must be called in the order requests are made.
+fn take_navigation_state(app: &AppHandle, webview_label: &str) -> (Option<u32>, bool) {
+    app.state::<BrowsingSessions>()
+        .with_session(webview_label, |session| {
+            (
+                Some(session.version_requested()),
+                session.take_next_load_is_address_bar(),
+            )
+        })
+}
+
 /// Fetch using a webname URL for website versions (awe://)
+/// The name is resolved to a HistoryAddress using the NameRegistry and the
+/// request is then served exactly as the equivalent awv:// URL would be.
 /// Returns content as an http Response
 async fn handle_protocol_awe(
+    app: &AppHandle,
+    webview_label: &str,
     req: &Request<Vec<u8>>,
     version_requested: Option<u32>,
+    loading_new_page_via_address_bar: bool,
 ) -> http::Response<Vec<u8>> {
     println!("DEBUG Hello from handle_protocol_awe() version_requested {version_requested:?}");
-    let url = req.uri();
-    let content =
-        format!("<HTML><HEAD></HEAD><BODY><h1>Handling Autonomi Request</h1>{url:?}</BODY></HTML>");
-    http::Response::builder()
-        .body(content.into_bytes())
-        .expect("http::Response::builder() failed")
+
+    let awe_url = match parse_request_url(req) {
+        Ok(awe_url) => awe_url,
+        Err(response) => return response,
+    };
+    let name = awe_url.host();
+
+    let Some(name_registry) = app.state::<Option<NameRegistry>>().inner() else {
+        let message = String::from("Web names are not available");
+        return ProtocolError::Other(StatusCode::SERVICE_UNAVAILABLE, message)
+            .to_response(&req.uri().to_string());
+    };
+
+    let client = client_or_offline(app).await;
+    let content_cache = app.state::<Option<ContentCache>>();
+    let history_address = match name_registry
+        .resolve(client.as_ref(), content_cache.as_ref(), name)
+        .await
+    {
+        Ok(history_address) => history_address,
+        Err(e) => {
+            let message = format!("Failed to resolve web name '{name}': {e}");
+            return ProtocolError::NotFound(message).to_response(&req.uri().to_string());
+        }
+    };
+
+    // Serve as the equivalent awv:// URL, keeping the version, path, query and request headers.
+    // The session still records the awe:// URL, as that is what the user visited.
+    let awv_url = awe_url
+        .with_host(AweScheme::History, &history_address.to_hex())
+        .to_string();
+    println!("DEBUG awe://{name} served as {awv_url}");
+
+    let mut awv_request = Request::builder().method(req.method()).uri(awv_url);
+    for (header_name, value) in req.headers() {
+        awv_request = awv_request.header(header_name, value);
+    }
+    let awv_request = match awv_request.body(req.body().clone()) {
+        Ok(awv_request) => awv_request,
+        Err(e) => {
+            return ProtocolError::BadRequest(format!("Failed to parse URL: {e}"))
+                .to_response(&req.uri().to_string())
+        }
+    };
+
+    handle_protocol_awv(
+        app,
+        webview_label,
+        &awv_request,
+        Some(&awe_url),
+        version_requested,
+        loading_new_page_via_address_bar,
+    )
+    .await
 }
 
 /// Fetch using xor URL for website versions (awv://)
+/// page_url is the URL the webview asked for if that was not req (e.g. an awe:// URL), and
+/// is what the session records and redirects are relative to.
 /// Returns content as an http Response
 async fn handle_protocol_awv(
+    app: &AppHandle,
+    webview_label: &str,
     req: &Request<Vec<u8>>,
+    page_url: Option<&AweUrl>,
     version_requested: Option<u32>,
+    loading_new_page_via_address_bar: bool,
 ) -> http::Response<Vec<u8>> {
     println!("DEBUG Hello from handle_protocol_awv() version_requested {version_requested:?}");
     let url = req.uri();
     println!("DEBUG url '{url}'");
 
-    let (_protocol, host_xor_string, resource_path, url_params) =
-        match parse_url_string(req.uri().to_string()) {
-            Ok((_protocol, host_xor_string, resource_path, url_params)) => {
-                (_protocol, host_xor_string, resource_path, url_params)
-            }
-            Err(e) => {
-                return http::Response::builder()
-                    .status(StatusCode::BAD_REQUEST)
-                    .body(format!("Failed to parse URL, error: {:?}", e).into_bytes())
-                    .unwrap()
-            }
-        };
-
-    // Loading URI via address bar:
-    let loading_new_page_via_address_bar = get_next_load_is_address_bar();
-    set_next_load_is_address_bar(false);
+    let awe_url = match parse_request_url(req) {
+        Ok(awe_url) => awe_url,
+        Err(response) => return response,
+    };
+    let page_url = page_url.unwrap_or(&awe_url);
+    let resource_path = awe_url.resource_path();
+    let url_params = awe_url.query_params();
+
+    let sessions = app.state::<BrowsingSessions>();
+    let (save_next_site_address, last_site_address, current_site_version) =
+        sessions.with_session(webview_label, |session| {
+            (
+                session.save_next_site_address(),
+                session.site_address(),
+                session.version_loaded(),
+            )
+        });
 
     // Loading due to click on link, or in page JavaScript:
-    let loading_new_page_via_page =
-        get_save_next_site_address() && !loading_new_page_via_address_bar;
+    let loading_new_page_via_page = save_next_site_address && !loading_new_page_via_address_bar;
 
     // Loading into current page:
     let loading_resource = !loading_new_page_via_address_bar && !loading_new_page_via_page;
 
-    let mut xor_host_differs_from_page = false;
-    let last_site_address = get_last_site_address();
-    if let Some(position) = last_site_address.find(host_xor_string.as_str()) {
-        if position != AWE_PROTOCOL_HISTORY.len() {
-            xor_host_differs_from_page = true;
-        };
-    } else {
-        xor_host_differs_from_page = true;
-    }
+    let xor_host_differs_from_page = match AweUrl::parse(&last_site_address) {
+        Ok(last_site_url) => {
+            last_site_url.scheme() != page_url.scheme() || last_site_url.host() != page_url.host()
+        }
+        Err(_) => true,
+    };
 
     let mut website_version = version_requested;
 
@@ -400,251 +543,584 @@ async fn handle_protocol_awv(
         }
     }
 
+    // A version pinned in the host overrides everything, and because it is part of the
+    // host it stays with every relative link on the page
+    let version_pin = awe_url.version();
+    if let Some(VersionPin::Version(version)) = version_pin {
+        website_version = Some(*version);
+    }
+
     if loading_resource && !xor_host_differs_from_page && website_version.is_none() {
-        if get_version_loaded() > 0 {
-            website_version = Some(get_version_loaded());
+        if current_site_version > 0 {
+            website_version = Some(current_site_version);
         }
     }
 
     if loading_new_page_via_page && !xor_host_differs_from_page && website_version.is_none() {
-        if get_version_loaded() > 0 {
-            website_version = Some(get_version_loaded());
+        if current_site_version > 0 {
+            website_version = Some(current_site_version);
         }
     }
 
-    println!("DEBUG (host_xor_string, resource_path): ({host_xor_string}, {resource_path})'");
-    let versions_history_address = match awe_str_to_history_address(&host_xor_string.as_str()) {
+    println!(
+        "DEBUG (host_xor_string, resource_path): ({}, {resource_path})'",
+        awe_url.host()
+    );
+    let versions_history_address = match awe_url.history_address() {
         Ok(versions_history_address) => versions_history_address,
         Err(err) => {
             let message = format!("Failed to parse HistoryAddress address [{:?}]", err);
-            println!("{message}");
-            return http::Response::builder()
-                .status(StatusCode::BAD_REQUEST)
-                .body(message.into_bytes())
-                .unwrap();
+            return ProtocolError::BadAddress(message).to_response(&req.uri().to_string());
         }
     };
 
-    // Initialise network connection, client and files api
-    let client = connect_to_autonomi()
-        .await
-        .expect("Failed to connect to Autonomi Network");
+    let client = client_or_offline(app).await;
 
-    // Save in case we don't want site version changed
-    let current_site_version = get_version_loaded();
+    if let Some(time) = version_pin.and_then(|version_pin| version_pin.live_at()) {
+        match app
+            .state::<HistoryCache>()
+            .resolve_at(client.as_ref(), versions_history_address, time)
+            .await
+        {
+            Ok((version, _)) => {
+                println!("DEBUG version live at {time} is {version}");
+                website_version = Some(version);
+            }
+            Err(status_code) => {
+                let message = format!("No version of the website was live at {time}");
+                return ProtocolError::from_status(status_code, message)
+                    .to_response(&req.uri().to_string());
+            }
+        }
+    }
 
-    let (datamap_chunk, data_address, content_type) = match awe_lookup_resource_for_website_version(
-        &client,
-        &resource_path,
-        versions_history_address,
-        website_version,
-    )
-    .await
-    {
-        Ok(result) => result,
+    // A listing asked for with ?list=1 is shown even if the directory has an index
+    let list_requested = listing_requested(&url_params);
+    let lookup = if list_requested {
+        Err(StatusCode::NOT_FOUND)
+    } else {
+        awe_lookup_resource_for_website_version(
+            &app.state::<HistoryCache>(),
+            &app.state::<SiteConfigs>(),
+            client.as_ref(),
+            app.state::<Option<ContentCache>>().as_ref(),
+            &resource_path,
+            &accepted_encodings(req),
+            versions_history_address,
+            website_version,
+        )
+        .await
+    };
+
+    let (response, resolved_version) = match lookup {
+        Ok((SiteLookup::Redirect(location, status), resolved_version)) => (
+            site_redirect_response(&page_url.site_root(), &location, status),
+            resolved_version,
+        ),
+        Ok((
+            SiteLookup::File(datamap_chunk, data_address, content_type, found_path, encoding),
+            resolved_version,
+        )) => {
+            // Only a URL which names its version always refers to the same content
+            let cache_control = if url_params.contains_key(URL_PARAM_VERSION)
+                || matches!(version_pin, Some(VersionPin::Version(_)))
+            {
+                CacheControl::Immutable
+            } else {
+                CacheControl::ShortLived(app.state::<HistoryCache>().latest_ttl().as_secs())
+            };
+
+            let (datamap_chunk, data_address) =
+                datamap_and_address_from_hex(datamap_chunk, data_address);
+            let response = awe_fetch_xor_data(
+                app,
+                req,
+                datamap_chunk,
+                data_address,
+                content_type,
+                Some(&found_path),
+                encoding,
+                cache_control,
+            )
+            .await;
+            (response, resolved_version)
+        }
+        Err(StatusCode::NOT_FOUND) if app.state::<DirectoryListing>().enabled_for(&url_params) => {
+            let listing = match app
+                .state::<HistoryCache>()
+                .resolve(client.as_ref(), versions_history_address, website_version)
+                .await
+            {
+                Ok((resolved_version, tree)) => {
+                    list_directory(&tree, &resource_path).map(|entries| (entries, resolved_version))
+                }
+                Err(_) => None,
+            };
+            match listing {
+                Some((entries, resolved_version)) => (
+                    listing_response(&url.to_string(), &resource_path, &entries, list_requested),
+                    resolved_version,
+                ),
+                None => {
+                    let message = format!("Resource not found at {resource_path}");
+                    (
+                        ProtocolError::NotFound(message).to_response(&url.to_string()),
+                        0,
+                    )
+                }
+            }
+        }
         Err(status_code) => {
             let message = format!("Resource not found at {resource_path}");
-            println!("{message}");
-            return http::Response::builder()
-                .status(status_code)
-                .body(message.into_bytes())
-                .unwrap();
+            let response =
+                ProtocolError::from_status(status_code, message).to_response(&url.to_string());
+            (response, 0)
         }
     };
 
-    let (datamap_chunk, data_address) = datamap_and_address_from_hex(datamap_chunk, data_address);
-    let mut response = awe_fetch_xor_data(Some(&client), datamap_chunk, data_address).await;
-    if response.status() == StatusCode::OK {
-        // Keep site version unchanged when loading a resource
-        if loading_resource {
-            set_version_loaded(current_site_version);
-        }
+    let response = if is_error_status(response.status()) {
+        let tree = app
+            .state::<HistoryCache>()
+            .resolve(client.as_ref(), versions_history_address, website_version)
+            .await
+            .ok()
+            .map(|(_, tree)| tree);
+        with_site_error_document(app, req, client.as_ref(), tree.as_deref(), response).await
+    } else {
+        response
+    };
 
-        // After loading a new page update the site address
-        if loading_new_page_via_address_bar
-            || loading_new_page_via_page && xor_host_differs_from_page
-        {
-            set_last_site_address(&url.to_string());
-        }
-    }
+    // The latest version may be out of date when offline, so say when it was resolved
+    let offline_status = client.is_none().then(|| OfflineStatus {
+        latest_resolved_at: match website_version {
+            None => app
+                .state::<HistoryCache>()
+                .latest_resolved_at(versions_history_address),
+            Some(_) => None,
+        },
+    });
+    let response = with_offline_headers(response, offline_status.as_ref());
+
+    if response.status().is_success() {
+        sessions.with_session(webview_label, |session| {
+            // Keep site version unchanged when loading a resource
+            if !loading_resource {
+                session.set_version_loaded(resolved_version);
+                session.set_offline_status(offline_status);
+                if website_version.is_none() && resolved_version > session.version_max() {
+                    session.set_version_max(resolved_version);
+                }
+            }
 
-    if let Some(content_type) = mime_guess::from_path(resource_path).first_raw() {
-        if let Ok(content_type) = header::HeaderValue::from_str(&content_type) {
-            response
-                .headers_mut()
-                .append(header::CONTENT_TYPE, content_type);
-        };
+            // After loading a new page update the site address
+            if loading_new_page_via_address_bar
+                || loading_new_page_via_page && xor_host_differs_from_page
+            {
+                session.set_site_address(&page_url.to_string());
+            }
+        });
     }
 
-    response
+    apply_range_request(req, response)
 }
 
 /// Fetch using an xor URL for a website (WebsiteMetadata) (awm://)
 /// Returns content as an http Response
-async fn handle_protocol_awm(req: &Request<Vec<u8>>) -> http::Response<Vec<u8>> {
+async fn handle_protocol_awm(
+    app: &AppHandle,
+    webview_label: &str,
+    req: &Request<Vec<u8>>,
+) -> http::Response<Vec<u8>> {
     println!("DEBUG Hello from handle_protocol_awm()");
 
     let url = req.uri().to_string();
     println!("DEBUG url '{url}'");
-    let (_, remainder) = if url.starts_with(PROTOCOL_AWM) {
-        url.split_at(PROTOCOL_AWM.len())
-    } else {
-        ("", url.as_str())
+    let awe_url = match parse_request_url(req) {
+        Ok(awe_url) => awe_url,
+        Err(response) => return response,
     };
+    let resource_path = awe_url.resource_path();
+    let url_params = awe_url.query_params();
 
-    let mut remainder = remainder.to_string();
-    let (address_string, resource_path) = match remainder.find(ARCHIVE_PATH_SEPARATOR) {
-        Some(separator_position) => {
-            let path_part = remainder.split_off(separator_position);
-            (remainder, path_part)
-        }
-        None => (remainder, String::from(ARCHIVE_PATH_SEPARATOR)),
-    };
-
-    println!("DEBUG (address_string, resource_path): ({address_string}, {resource_path})'");
-    let address = match awe_str_to_data_address(&address_string.as_str()) {
+    println!(
+        "DEBUG (address, resource_path): ({}, {resource_path})'",
+        awe_url.host()
+    );
+    let address = match awe_url.data_address() {
         Ok(address) => address,
         Err(err) => {
             let message = format!("Failed to parse hex address. [{:?}]", err);
-            println!("{message}");
-            return http::Response::builder()
-                .status(StatusCode::BAD_REQUEST)
-                .body(message.into_bytes())
-                .unwrap();
+            return ProtocolError::BadAddress(message).to_response(&req.uri().to_string());
         }
     };
 
-    // Initialise network connection, client and files api
-    let client = connect_to_autonomi()
+    let client = client_or_offline(app).await;
+    let file_tree = match app
+        .state::<HistoryCache>()
+        .archive_tree(client.as_ref(), address)
         .await
-        .expect("Failed to connect to Autonomi Network");
-
-    println!("DEBUG calling Tree::from_archive_address()");
-    let file_tree = match Tree::from_archive_address(&client, address).await {
+    {
         Ok(file_tree) => {
             println!("DEBUG got file_tree");
             file_tree
         }
         Err(err) => {
-            let message = format!("Failed to parse XOR address. [{:?}]", err);
-            println!("{message}");
-            return http::Response::builder()
-                .status(StatusCode::BAD_REQUEST)
-                .body(message.into_bytes())
-                .unwrap();
+            return ProtocolError::from_fetch_error(&err).to_response(&req.uri().to_string());
         }
     };
 
-    let response = match file_tree.lookup_file(&resource_path, true) {
-        Ok((datamap_chunk, data_address, content_type)) => {
-            match get_content_using_hex(&client, datamap_chunk, data_address).await {
-                Ok(content) => {
-                    let mut response = http::Response::builder().status(200);
-                    if let Some(content_type) = content_type {
-                        if let Ok(content_type) = header::HeaderValue::from_str(&content_type) {
-                            response
-                                .headers_mut()
-                                .unwrap()
-                                .insert("Content-Type", content_type);
-                        }
-                    }
-                    response.body(content.into()).unwrap()
-                }
-                Err(e) => {
-                    let message = format!("Faild to get content {resource_path} - {e}");
-                    println!("{message}");
-                    return http::Response::builder()
-                        .status(StatusCode::BAD_GATEWAY)
-                        .body(message.into_bytes())
-                        .unwrap();
+    // A listing asked for with ?list=1 is shown even if the directory has an index
+    let list_requested = listing_requested(&url_params);
+    let lookup = if list_requested {
+        Err(StatusCode::NOT_FOUND)
+    } else {
+        let content_cache = app.state::<Option<ContentCache>>();
+        let site_config = app
+            .state::<SiteConfigs>()
+            .for_tree(client.as_ref(), content_cache.as_ref(), &file_tree)
+            .await;
+        lookup_with_rules(
+            &file_tree,
+            &site_config,
+            &resource_path,
+            &accepted_encodings(req),
+        )
+    };
+
+    let response = match lookup {
+        Ok(SiteLookup::Redirect(location, status)) => {
+            site_redirect_response(&awe_url.site_root(), &location, status)
+        }
+        Ok(SiteLookup::File(datamap_chunk, data_address, content_type, found_path, encoding)) => {
+            let (datamap_chunk, data_address) =
+                datamap_and_address_from_hex(datamap_chunk, data_address);
+            awe_fetch_xor_data(
+                app,
+                req,
+                datamap_chunk,
+                data_address,
+                content_type,
+                Some(&found_path),
+                encoding,
+                CacheControl::Immutable,
+            )
+            .await
+        }
+        Err(StatusCode::NOT_FOUND) if app.state::<DirectoryListing>().enabled_for(&url_params) => {
+            match list_directory(&file_tree, &resource_path) {
+                Some(entries) => listing_response(&url, &resource_path, &entries, list_requested),
+                None => {
+                    let message = format!("Tree lookup failed for {resource_path}");
+                    ProtocolError::NotFound(message).to_response(&url)
                 }
             }
         }
         Err(status_code) => {
             let message = format!("Tree lookup failed for {resource_path}");
-            println!("{message}");
-            return http::Response::builder()
-                .status(status_code)
-                .body(message.into_bytes())
-                .unwrap();
+            ProtocolError::from_status(status_code, message).to_response(&url)
         }
     };
-
-    if response.status() == StatusCode::OK {
-        set_last_site_address(&url.to_string());
+    let response = with_site_error_document(
+        app,
+        req,
+        client.as_ref(),
+        Some(file_tree.as_ref()),
+        response,
+    )
+    .await;
+
+    // A directory never changes, so there is no latest version to go out of date
+    let offline_status = client.is_none().then_some(OfflineStatus {
+        latest_resolved_at: None,
+    });
+    let response = with_offline_headers(response, offline_status.as_ref());
+
+    if response.status().is_success() {
+        app.state::<BrowsingSessions>()
+            .with_session(webview_label, |session| {
+                session.set_site_address(&url);
+                session.set_offline_status(offline_status);
+            });
     }
 
-    response
+    apply_range_request(req, response)
 }
 
 /// Fetch a file using just an xor address (awf://)
 /// Returns content as an http Response
-async fn handle_protocol_awf(req: &Request<Vec<u8>>) -> http::Response<Vec<u8>> {
+async fn handle_protocol_awf(app: &AppHandle, req: &Request<Vec<u8>>) -> http::Response<Vec<u8>> {
     println!("DEBUG Hello from handle_protocol_awf()");
 
-    // Initialise network connection, client and files api
-    let client = connect_to_autonomi()
-        .await
-        .expect("Failed to connect to Autonomi Network");
+    let awe_url = match parse_request_url(req) {
+        Ok(awe_url) => awe_url,
+        Err(response) => return response,
+    };
+    let url_params = awe_url.query_params();
 
-    // TODO test if need to handle trailing slash
-    let autonomi_url = req.uri().to_string();
-    let data_address = match awe_str_to_data_address(&autonomi_url.as_str()) {
+    let data_address = match awe_url.data_address() {
         Ok(data_address) => data_address,
         Err(err) => {
             let message = format!("Failed to parse XOR address. [{:?}]", err);
-            println!("{message}");
-            return http::Response::builder()
-                .status(StatusCode::BAD_REQUEST)
-                .body(message.into_bytes())
-                .unwrap();
+            return ProtocolError::BadAddress(message).to_response(&req.uri().to_string());
         }
     };
 
-    return awe_fetch_xor_data(Some(&client), None, Some(data_address)).await;
+    // Allow the URL to say how the file should be treated (e.g. awf://<FILE-ADDRESS>?type=image/png)
+    let content_type = url_params.get(URL_PARAM_CONTENT_TYPE).cloned();
+
+    let response = awe_fetch_xor_data(
+        app,
+        req,
+        None,
+        Some(data_address),
+        content_type,
+        None,
+        None,
+        CacheControl::Immutable,
+    )
+    .await;
+    apply_range_request(req, response)
+}
+
+/// True for a status which may be replaced by the site's own error document
+fn is_error_status(status: StatusCode) -> bool {
+    (status.is_client_error() || status.is_server_error())
+        && status != StatusCode::RANGE_NOT_SATISFIABLE
 }
 
-/// Fetch data from network and return as an http Response
+/// Replace an error response with the site's own document for that status, if the
+/// Tree has one. This is either set in the [errors] table of awe.toml or is one of the
+/// conventional /404.html or /404/index.html. The document is served with the status
+/// of the original response, which is returned unchanged if there is no such document.
+async fn with_site_error_document(
+    app: &AppHandle,
+    req: &Request<Vec<u8>>,
+    client: Option<&DwebClient>,
+    tree: Option<&Tree>,
+    response: http::Response<Vec<u8>>,
+) -> http::Response<Vec<u8>> {
+    let status = response.status();
+    let Some(tree) = tree.filter(|_| is_error_status(status)) else {
+        return response;
+    };
+
+    let content_cache = app.state::<Option<ContentCache>>();
+    let site_config = app
+        .state::<SiteConfigs>()
+        .for_tree(client, content_cache.as_ref(), tree)
+        .await;
+
+    for path in site_config.error_document_paths(status) {
+        let Ok((datamap_chunk, data_address, content_type)) = tree.lookup_file(&path, false) else {
+            continue;
+        };
+        let (datamap_chunk, data_address) =
+            datamap_and_address_from_hex(datamap_chunk, data_address);
+        let document = awe_fetch_xor_data(
+            app,
+            req,
+            datamap_chunk,
+            data_address,
+            content_type,
+            Some(&path),
+            None,
+            CacheControl::Immutable,
+        )
+        .await;
+
+        if document.status() == StatusCode::OK {
+            println!("DEBUG serving error document {path} for status {status}");
+            let (mut parts, body) = document.into_parts();
+            parts.status = status;
+            parts.headers.remove(header::ETAG);
+            parts.headers.insert(
+                header::CACHE_CONTROL,
+                header::HeaderValue::from_static("no-store"),
+            );
+            return http::Response::from_parts(parts, body);
+        }
+    }
+
+    response
+}
+
+/// Response sending the browser to location, which is relative to site_root
+/// unless it is a full URL
+fn site_redirect_response(
+    site_root: &str,
+    location: &str,
+    status: StatusCode,
+) -> http::Response<Vec<u8>> {
+    let location = if location.contains(PROTOCOL_END_STR) {
+        location.to_string()
+    } else {
+        format!("{site_root}/{}", location.trim_start_matches('/'))
+    };
+    http::Response::builder()
+        .status(status)
+        .header(header::LOCATION, location)
+        .header(header::CACHE_CONTROL, "no-cache")
+        .body(Vec::new())
+        .unwrap()
+}
+
+/// Return the shared client, or None when offline so that requests are served from the cache
+async fn client_or_offline(app: &AppHandle) -> Option<DwebClient> {
+    match app.state::<SharedClient>().get().await {
+        Ok(client) => Some(client),
+        Err(e) => {
+            println!("DEBUG serving from cache: {e}");
+            None
+        }
+    }
+}
+
+/// Mark a response served while offline, with the Age of the latest version if known
+fn with_offline_headers(
+    response: http::Response<Vec<u8>>,
+    offline_status: Option<&OfflineStatus>,
+) -> http::Response<Vec<u8>> {
+    let Some(offline_status) = offline_status else {
+        return response;
+    };
+
+    let (mut parts, body) = response.into_parts();
+    parts.headers.insert(
+        OFFLINE_HEADER,
+        header::HeaderValue::from_static(OFFLINE_HEADER_VALUE),
+    );
+    if let Some(age) = offline_status
+        .latest_resolved_at
+        .and_then(|resolved_at| resolved_at.elapsed().ok())
+    {
+        parts
+            .headers
+            .insert(header::AGE, header::HeaderValue::from(age.as_secs()));
+    }
+    http::Response::from_parts(parts, body)
+}
+
+/// Fetch data from the content cache or network and return as an http Response
+/// The Content-Type is set using content_type_for() from content_type (if known),
+/// the resource_path and finally the content itself.
+/// If the data is a compressed copy of the resource, content_encoding says how it was
+/// compressed so that the webview can decode it.
+/// A file larger than STREAMING_THRESHOLD is never held whole. A request for it is answered
+/// with 206 Partial Content holding at most STREAM_WINDOW bytes, from the start of the Range
+/// asked for or of the file if there is no Range header, and the webview asks for the rest.
+/// Responses carry an ETag based on the address of the data, and if the request
+/// already holds that ETag a 304 Not Modified is returned without fetching anything.
+/// If the network fails the shared client is reset so that the next request reconnects
 async fn awe_fetch_xor_data(
-    client_opt: Option<&DwebClient>,
+    app: &AppHandle,
+    req: &Request<Vec<u8>>,
     datamap_chunk: Option<DataMapChunk>,
     data_address: Option<DataAddress>,
+    content_type: Option<String>,
+    resource_path: Option<&String>,
+    content_encoding: Option<ContentEncoding>,
+    cache_control: CacheControl,
 ) -> http::Response<Vec<u8>> {
     println!(
         "DEBUG awe_fetch_xor_data() using data_address: {:?} or datamap_chunk: {:?}",
         data_address, datamap_chunk
     );
 
-    let client;
-    let client_ref;
-    if let Some(api_ref) = client_opt {
-        client_ref = api_ref;
-    } else {
-        client = connect_to_autonomi()
-            .await
-            .expect("Failed to connect to Autonomi Network");
-        client_ref = &client;
+    let etag = etag_for(&datamap_chunk, &data_address);
+    if let Some(etag) = &etag {
+        if request_matches_etag(req, etag) {
+            println!("DEBUG not modified: {etag}");
+            return not_modified_response(etag, cache_control);
+        }
     }
 
-    // TODO since Tauri v2, the iframe won't load content from
-    // TODO a URI unless the response has a Content-Type header
-    // TODO Investigate options, such as saving content type in the site map
-    match get_content(&client_ref, datamap_chunk, data_address).await {
-        Ok(content) => {
-            println!("DEBUG retrieved {} bytes", content.len());
-            return http::Response::builder()
-                .header(http::header::CONTENT_TYPE, "text/html") // TODO needed since Tauri switched to using http::Response from tauri::http::ResponseBuilder
-                .body(content.to_vec())
-                .unwrap();
-        }
-        Err(e) => {
-            let message = format!("{e}");
-            return http::Response::builder()
-                .status(StatusCode::BAD_GATEWAY)
-                .body(message.into_bytes())
-                .unwrap();
+    let shared_client = app.state::<SharedClient>();
+    let client = client_or_offline(app).await;
+    let content_cache = app.state::<Option<ContentCache>>();
+    let fetched = get_content_or_stream_cached(
+        client.as_ref(),
+        content_cache.as_ref(),
+        datamap_chunk,
+        data_address,
+    )
+    .await;
+
+    // A large file is sent a window at a time
+    let (content, content_range) = match fetched {
+        Ok(FetchedContent::Whole(content)) => (content, None),
+        Ok(FetchedContent::Stream(stream)) => {
+            let read = match streamed_range(req, stream.size(), STREAM_WINDOW) {
+                ByteRange::Partial(start, end) => stream
+                    .read_range(start, end + 1 - start)
+                    .await
+                    .map(|window| (window, Some((start, end, stream.size())))),
+                // Only for an empty body, which is never streamed
+                ByteRange::Full => Ok((Bytes::new(), None)),
+                ByteRange::Unsatisfiable => return range_not_satisfiable_response(stream.size()),
+            };
+            match read {
+                Ok(read) => read,
+                Err(e) => return fetch_failed_response(&shared_client, req, &e).await,
+            }
         }
+        Err(e) => return fetch_failed_response(&shared_client, req, &e).await,
+    };
+    println!("DEBUG retrieved {} bytes", content.len());
+
+    // Since Tauri v2, the iframe won't load content from a URI
+    // unless the response has a Content-Type header.
+    // Compressed bytes, or those from part way through a file, say nothing about its type
+    let sniffable: &[u8] = if content_encoding.is_some()
+        || matches!(content_range, Some((start, _, _)) if start > 0)
+    {
+        &[]
+    } else {
+        &content
+    };
+    let content_type = content_type_for(
+        content_type.as_deref(),
+        resource_path.map(|path| path.as_str()),
+        sniffable,
+    );
+    // Whether a compressed copy is sent depends on Accept-Encoding, so a cached
+    // response must not be reused for a request which accepts different encodings
+    let mut response = http::Response::builder()
+        .header(header::CONTENT_TYPE, content_type)
+        .header(header::CACHE_CONTROL, cache_control.header_value())
+        .header(header::VARY, "Accept-Encoding");
+    if let Some(etag) = etag {
+        response = response.header(header::ETAG, etag);
+    }
+    if let Some(content_encoding) = content_encoding {
+        response = response.header(header::CONTENT_ENCODING, content_encoding.header_value());
+    }
+    if client.is_none() {
+        response = response.header(OFFLINE_HEADER, OFFLINE_HEADER_VALUE);
+    }
+    if let Some((start, end, size)) = content_range {
+        println!("DEBUG streaming bytes {start}-{end} of {size}");
+        response = response
+            .status(StatusCode::PARTIAL_CONTENT)
+            .header(header::ACCEPT_RANGES, "bytes")
+            .header(header::CONTENT_RANGE, format!("bytes {start}-{end}/{size}"));
+    }
+    response.body(Vec::from(content)).unwrap()
+}
+
+/// Return the error response for a failed fetch
+///
+/// The shared client is only reset when the failure is with the network itself, so
+/// that data which is missing, not cached or at a bad address doesn't force a reconnect.
+async fn fetch_failed_response(
+    shared_client: &SharedClient,
+    req: &Request<Vec<u8>>,
+    error: &Report,
+) -> http::Response<Vec<u8>> {
+    let protocol_error = ProtocolError::from_fetch_error(error);
+    if let ProtocolError::NetworkUnavailable(_) = protocol_error {
+        shared_client.reset().await;
     }
+    protocol_error.to_response(&req.uri().to_string())
 }
 
 // TODO Improve autonomi application level API errors (e.g. in a crate, or in the Autonomi APIs).
@@ -683,41 +1159,37 @@ pub fn tauri_http_status_from_network_error(error: &GetError) -> (StatusCode, St
 
 /// Look-up a website resource in a Tree obtained from a History on the network
 /// according to Some(version), or the most recent version if None.
+/// The History is resolved using the HistoryCache, so only the first lookup for
+/// a version (or for the latest version within its time to live) uses the network.
 /// The lookup automatically handles a resource_path which ends in '/', and so will return
 /// '/index.html' or '/index.htm' if found (or other defaults according to website settings in the Tree).
-/// Updates the loaded version
-/// Returns DataMapChunk or DataAddress as hex strings if present and the content type if known
+/// Any redirect and rewrite rules published with the site (in awe.toml or _redirects) are applied,
+/// and a compressed copy of the file is used if it has one in any of the accepted encodings.
+/// Returns the file found (or a redirect) and the version of the website which was used
 pub async fn awe_lookup_resource_for_website_version(
-    client: &DwebClient,
+    history_cache: &HistoryCache,
+    site_configs: &SiteConfigs,
+    client: Option<&DwebClient>,
+    content_cache: Option<&ContentCache>,
     resource_path: &String,
+    encodings: &[ContentEncoding],
     history_address: HistoryAddress,
     version: Option<u32>,
-) -> Result<(String, String, Option<String>), StatusCode> {
+) -> Result<(SiteLookup, u32), StatusCode> {
     println!("DEBUG lookup_resource_for_website_version() version {version:?}");
     println!("DEBUG history_address: {}", history_address.to_hex());
     println!("DEBUG resource_path    : {resource_path}");
 
-    match History::<Tree>::from_history_address(client.clone(), history_address, false, 0).await {
-        Ok(mut history) => {
-            match Tree::history_lookup_file(&mut history, resource_path, true, version).await {
-                Ok(result) => {
-                    let trove_version = history.get_cached_version();
-                    set_version_loaded(if trove_version.is_none() {
-                        0
-                    } else {
-                        trove_version.unwrap().version
-                    });
-                    Ok(result)
-                }
-                Err(e) => {
-                    println!("Lookup web resource failed: {e:?}");
-                    return Err(e);
-                }
-            }
-        }
+    let (resolved_version, tree) = history_cache
+        .resolve(client, history_address, version)
+        .await?;
+
+    let site_config = site_configs.for_tree(client, content_cache, &tree).await;
+    match lookup_with_rules(&tree, &site_config, resource_path, encodings) {
+        Ok(site_lookup) => Ok((site_lookup, resolved_version)),
         Err(e) => {
-            println!("Failed to load History: {e:?}");
-            return Err(StatusCode::NOT_FOUND);
+            println!("Lookup web resource failed: {e:?}");
+            return Err(e);
         }
     }
 }
@@ -0,0 +1,239 @@
+/*
+Copyright (c) 2024-2025 Mark Hughes
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+/// The most addresses kept for back and forward in each session
+const MAX_NAVIGATION_STACK: usize = 100;
+
+/// Set for a page which was served from the cache because the browser is offline
+#[derive(Clone, Debug)]
+pub struct OfflineStatus {
+    /// When the latest version of the site was last resolved from the network, if the
+    /// page is from the latest version
+    pub latest_resolved_at: Option<SystemTime>,
+}
+
+/// Navigation state for the browser in one webview
+///
+/// The protocol handlers use this to decide whether a request is for a new page
+/// or for a resource of the current page, and so which version of a site to load.
+#[derive(Clone, Debug)]
+pub struct BrowsingSession {
+    /// Set by the UI when it is about to navigate by setting the source URI
+    next_load_is_address_bar: bool,
+    /// Set by the UI after a page loads, so we know the next load is a new page rather than a page resource
+    save_next_site_address: bool,
+    /// Address of the site loaded via the address bar or by clicking a link
+    site_address: String,
+    /// Version requested by the UI or on the CLI (0 for the most recent)
+    version_requested: u32,
+    /// Version of the current site, which is pinned for the resources of the page
+    version_loaded: u32,
+    /// Number of versions of the current site, or 0 if not known
+    version_max: u32,
+    /// Addresses of the sites loaded in this webview by the address bar or links, oldest first
+    navigation_stack: Vec<String>,
+    /// Index in navigation_stack of the current site
+    navigation_position: usize,
+    /// Set while loading an address from navigation_stack, so that it is not pushed again
+    navigating_stack: bool,
+    /// Set if the current page was served while offline
+    offline_status: Option<OfflineStatus>,
+}
+
+impl BrowsingSession {
+    pub fn new(version_requested: u32) -> BrowsingSession {
+        BrowsingSession {
+            next_load_is_address_bar: true,
+            save_next_site_address: true,
+            site_address: String::from(""),
+            version_requested,
+            version_loaded: 0,
+            version_max: 0,
+            navigation_stack: Vec::new(),
+            navigation_position: 0,
+            navigating_stack: false,
+            offline_status: None,
+        }
+    }
+
+    pub fn set_next_load_is_address_bar(&mut self, flag: bool) {
+        println!("DEBUG set_next_load_is_address_bar() set to {flag}");
+        self.next_load_is_address_bar = flag;
+    }
+
+    /// Return true if the next load is via the address bar, and clear the flag
+    /// so that subsequent loads are not
+    pub fn take_next_load_is_address_bar(&mut self) -> bool {
+        let next_load_is_address_bar = self.next_load_is_address_bar;
+        self.next_load_is_address_bar = false;
+        println!("DEBUG take_next_load_is_address_bar() returning {next_load_is_address_bar}");
+        next_load_is_address_bar
+    }
+
+    pub fn save_next_site_address(&self) -> bool {
+        self.save_next_site_address
+    }
+
+    pub fn set_save_next_site_address(&mut self, flag: bool) {
+        println!("DEBUG set_save_next_site_address() set to {flag}");
+        self.save_next_site_address = flag;
+    }
+
+    pub fn site_address(&self) -> String {
+        self.site_address.clone()
+    }
+
+    /// Record site_address as the current site if the UI asked us to save the next one
+    pub fn set_site_address(&mut self, site_address: &String) {
+        if self.save_next_site_address {
+            self.save_next_site_address = false;
+            println!("DEBUG set_site_address() set to {site_address}");
+            self.site_address = site_address.clone();
+            self.push_navigation(site_address);
+        }
+    }
+
+    /// Push site_address as the newest address, dropping any addresses that were ahead of
+    /// the current one (as a browser does after going back and then to a new page)
+    fn push_navigation(&mut self, site_address: &String) {
+        if self.navigating_stack {
+            self.navigating_stack = false;
+            return;
+        }
+        if self.navigation_stack.get(self.navigation_position) == Some(site_address) {
+            return;
+        }
+        if !self.navigation_stack.is_empty() {
+            self.navigation_stack.truncate(self.navigation_position + 1);
+        }
+        self.navigation_stack.push(site_address.clone());
+        if self.navigation_stack.len() > MAX_NAVIGATION_STACK {
+            self.navigation_stack.remove(0);
+        }
+        self.navigation_position = self.navigation_stack.len() - 1;
+    }
+
+    /// Move back through the navigation stack, returning the address to load if there is one
+    pub fn navigate_back(&mut self) -> Option<String> {
+        if self.navigation_position == 0 || self.navigation_stack.is_empty() {
+            return None;
+        }
+        self.navigation_position -= 1;
+        self.navigating_stack = true;
+        println!(
+            "DEBUG navigate_back() to position {}",
+            self.navigation_position
+        );
+        self.navigation_stack.get(self.navigation_position).cloned()
+    }
+
+    /// Move forward through the navigation stack, returning the address to load if there is one
+    pub fn navigate_forward(&mut self) -> Option<String> {
+        if self.navigation_position + 1 >= self.navigation_stack.len() {
+            return None;
+        }
+        self.navigation_position += 1;
+        self.navigating_stack = true;
+        println!(
+            "DEBUG navigate_forward() to position {}",
+            self.navigation_position
+        );
+        self.navigation_stack.get(self.navigation_position).cloned()
+    }
+
+    pub fn version_requested(&self) -> u32 {
+        self.version_requested
+    }
+
+    pub fn set_version_requested(&mut self, version: u32) {
+        println!("DEBUG set_version_requested() set to {version}");
+        self.version_requested = version;
+    }
+
+    pub fn version_loaded(&self) -> u32 {
+        self.version_loaded
+    }
+
+    pub fn set_version_loaded(&mut self, version: u32) {
+        println!("DEBUG set_version_loaded() set to {version}");
+        self.version_loaded = version;
+    }
+
+    pub fn version_max(&self) -> u32 {
+        self.version_max
+    }
+
+    pub fn set_version_max(&mut self, version: u32) {
+        println!("DEBUG set_version_max() set to {version}");
+        self.version_max = version;
+    }
+
+    pub fn offline_status(&self) -> Option<OfflineStatus> {
+        self.offline_status.clone()
+    }
+
+    pub fn set_offline_status(&mut self, offline_status: Option<OfflineStatus>) {
+        println!("DEBUG set_offline_status() set to {offline_status:?}");
+        self.offline_status = offline_status;
+    }
+}
+
+/// The BrowsingSession of each webview, keyed by webview label. Held in Tauri state.
+///
+/// Commands and protocol handlers must both use the label of the webview, since a window
+/// may hold more than one webview and its label need not match theirs.
+pub struct BrowsingSessions {
+    /// Version given on the CLI, used for each new session
+    cli_version: u32,
+    sessions: Mutex<HashMap<String, BrowsingSession>>,
+}
+
+impl BrowsingSessions {
+    pub fn new(cli_version: Option<u32>) -> BrowsingSessions {
+        BrowsingSessions {
+            cli_version: cli_version.unwrap_or(0),
+            sessions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Call f with the session for webview_label, creating the session if needed
+    pub fn with_session<R>(
+        &self,
+        webview_label: &str,
+        f: impl FnOnce(&mut BrowsingSession) -> R,
+    ) -> R {
+        let mut sessions = self.sessions.lock().unwrap();
+        let session = sessions
+            .entry(webview_label.to_string())
+            .or_insert_with(|| BrowsingSession::new(self.cli_version));
+        f(session)
+    }
+
+    /// Return the label and site address of each webview which has loaded a site
+    pub fn site_addresses(&self) -> Vec<(String, String)> {
+        let sessions = self.sessions.lock().unwrap();
+        sessions
+            .iter()
+            .filter(|(_, session)| !session.site_address.is_empty())
+            .map(|(label, session)| (label.clone(), session.site_address.clone()))
+            .collect()
+    }
+}
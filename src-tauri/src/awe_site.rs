@@ -0,0 +1,495 @@
+/*
+Copyright (c) 2024-2025 Mark Hughes
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+//! Settings published with a website, read from awe.toml, _redirects and _encodings in the
+//! root of the site
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+
+use http::status::StatusCode;
+use serde::Deserialize;
+
+use dweb::client::DwebClient;
+use dweb::files::directory::{datamap_and_address_from_hex, Tree};
+
+use crate::awe_cache::{get_content_cached, ContentCache};
+use crate::awe_compress::{CompressedCopies, COMPRESSED_COPIES_FILE};
+use crate::awe_http::ContentEncoding;
+
+pub const SITE_CONFIG_FILE: &str = "/awe.toml";
+pub const SITE_REDIRECTS_FILE: &str = "/_redirects";
+
+const SPLAT: &str = ":splat";
+
+/// The contents of awe.toml
+///
+/// For example:
+///
+///   # Serve /index.html for any unknown page (for client side routing)
+///   spa_fallback = "/index.html"
+///   # Resolve /about to /about.html or /about/index.html (the default)
+///   pretty_urls = true
+///
+///   [errors]
+///   404 = "/not-found.html"
+///   500 = "/oops.html"
+///
+///   [rewrites]
+///   "/app/*" = "/app/index.html"
+///
+///   [redirects]
+///   "/old-page" = "/new-page"
+///   "/blog/*" = "/posts/:splat 302"
+///
+/// Rules can also be given one per line in a _redirects file, as '<FROM> <TO> [STATUS]'
+/// where a STATUS of 200 is a rewrite and anything else a redirect (301 by default).
+///
+/// A rule only applies when no file is published at the path, unless its STATUS ends
+/// in '!' (e.g. "/blog/* /posts/:splat 301!") which forces it to apply regardless.
+#[derive(Debug, Default, Deserialize)]
+pub struct SiteConfig {
+    /// Error document to serve for each status code
+    #[serde(default)]
+    pub errors: HashMap<String, String>,
+    #[serde(default)]
+    rewrites: BTreeMap<String, String>,
+    #[serde(default)]
+    redirects: BTreeMap<String, String>,
+    spa_fallback: Option<String>,
+    pretty_urls: Option<bool>,
+    /// Rules from the tables above and _redirects, in the order they are tried
+    #[serde(skip)]
+    rules: Vec<SiteRule>,
+    /// Compressed copies listed in _encodings
+    #[serde(skip)]
+    compressed: CompressedCopies,
+}
+
+/// A rewrite or redirect from a path, or from all paths beginning with a prefix
+/// if from ends with '*'. The part matched by '*' replaces any ':splat' in to.
+#[derive(Clone, Debug)]
+struct SiteRule {
+    from: String,
+    to: String,
+    action: RuleAction,
+    /// Apply even when a file exists at the path
+    force: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum RuleAction {
+    Rewrite,
+    Redirect(StatusCode),
+}
+
+/// The result of looking up a path in a site according to its rules
+pub enum SiteLookup {
+    /// The DataMapChunk or DataAddress as hex strings, the content type if known,
+    /// the path which was found and, if the DataMapChunk or DataAddress are those of
+    /// a compressed copy of the file, its encoding
+    File(
+        String,
+        String,
+        Option<String>,
+        String,
+        Option<ContentEncoding>,
+    ),
+    /// Send the browser to a new location with this status
+    Redirect(String, StatusCode),
+}
+
+impl SiteConfig {
+    /// Parse the text of awe.toml, _redirects and _encodings, any of which may be absent
+    pub fn from_files(
+        config_text: Option<&str>,
+        redirects_text: Option<&str>,
+        copies_text: Option<&str>,
+    ) -> Result<SiteConfig, String> {
+        let mut config = match config_text {
+            Some(text) => toml::from_str::<SiteConfig>(text).map_err(|e| format!("{e}"))?,
+            None => SiteConfig::default(),
+        };
+
+        // In awe.toml exact paths are tried first, then the longest prefixes
+        let mut table_rules = Vec::<SiteRule>::new();
+        for (from, to) in &config.rewrites {
+            table_rules.push(SiteRule::new(from, to, RuleAction::Rewrite, false));
+        }
+        for (from, to) in &config.redirects {
+            let (to, action, force) = parse_target(to, StatusCode::MOVED_PERMANENTLY);
+            table_rules.push(SiteRule::new(from, &to, action, force));
+        }
+        table_rules.sort_by_key(|rule| match rule.from.strip_suffix('*') {
+            Some(prefix) => (1, usize::MAX - prefix.len()),
+            None => (0, 0),
+        });
+        config.rules = table_rules;
+
+        // In _redirects the first matching rule wins
+        if let Some(text) = redirects_text {
+            for line in text.lines() {
+                let line = line.trim();
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                let fields: Vec<&str> = line.split_whitespace().collect();
+                if fields.len() < 2 {
+                    println!("Ignoring line in {SITE_REDIRECTS_FILE}: '{line}'");
+                    continue;
+                }
+                let (to, action, force) =
+                    parse_target(&fields[1..].join(" "), StatusCode::MOVED_PERMANENTLY);
+                config
+                    .rules
+                    .push(SiteRule::new(fields[0], &to, action, force));
+            }
+        }
+
+        if let Some(text) = copies_text {
+            // Without the list the originals are served, so the rest of the site still works
+            match CompressedCopies::from_text(text) {
+                Ok(compressed) => config.compressed = compressed,
+                Err(e) => println!("Ignoring {COMPRESSED_COPIES_FILE}: {e}"),
+            }
+        }
+
+        Ok(config)
+    }
+
+    /// Return the paths to try for a document to show with status, most preferred first
+    ///
+    /// Any document configured in awe.toml comes first, followed by the conventional
+    /// /<CODE>.html and /<CODE>/index.html that static site generators produce.
+    pub fn error_document_paths(&self, status: StatusCode) -> Vec<String> {
+        let code = status.as_u16();
+        let mut paths = Vec::new();
+        if let Some(path) = self.errors.get(&code.to_string()) {
+            paths.push(format!("/{}", path.trim_start_matches('/')));
+        }
+        paths.push(format!("/{code}.html"));
+        paths.push(format!("/{code}/index.html"));
+        paths
+    }
+
+    /// Return the location and status of any redirect for resource_path, taken from
+    /// the forced rules if forced is true and otherwise from the rest
+    pub fn redirect_for(&self, resource_path: &str, forced: bool) -> Option<(String, StatusCode)> {
+        self.rules
+            .iter()
+            .filter(|rule| rule.force == forced)
+            .find_map(|rule| match rule.action {
+                RuleAction::Redirect(status) => {
+                    rule.target_for(resource_path).map(|to| (to, status))
+                }
+                RuleAction::Rewrite => None,
+            })
+    }
+
+    /// Return the targets of forced rewrites for resource_path, which are tried in
+    /// order before resource_path itself
+    pub fn forced_rewrite_paths(&self, resource_path: &str) -> Vec<String> {
+        self.rewrite_paths(resource_path, true)
+    }
+
+    fn rewrite_paths(&self, resource_path: &str, forced: bool) -> Vec<String> {
+        self.rules
+            .iter()
+            .filter(|rule| rule.action == RuleAction::Rewrite && rule.force == forced)
+            .filter_map(|rule| rule.target_for(resource_path))
+            .filter(|to| !to.contains("://"))
+            .collect()
+    }
+
+    /// Return the paths to try, in order, when resource_path is not found
+    ///
+    /// These are the targets of matching rewrites which are not forced, then (with pretty URLs)
+    /// the path with '.html' or '/index.html' added, and finally the SPA fallback.
+    /// The last two only apply to paths which don't end in a file extension, so
+    /// that a missing image or script is still reported as not found.
+    pub fn fallback_paths(&self, resource_path: &str) -> Vec<String> {
+        let mut paths = self.rewrite_paths(resource_path, false);
+
+        let last_segment = resource_path.rsplit('/').next().unwrap_or("");
+        if !last_segment.contains('.') {
+            let path = resource_path.trim_end_matches('/');
+            if self.pretty_urls.unwrap_or(true) && !path.is_empty() {
+                paths.push(format!("{path}.html"));
+                paths.push(format!("{path}/index.html"));
+            }
+            if let Some(spa_fallback) = &self.spa_fallback {
+                paths.push(format!("/{}", spa_fallback.trim_start_matches('/')));
+            }
+        }
+        paths
+    }
+}
+
+impl SiteRule {
+    fn new(from: &str, to: &str, action: RuleAction, force: bool) -> SiteRule {
+        SiteRule {
+            from: format!("/{}", from.trim_start_matches('/')),
+            to: to.to_string(),
+            action,
+            force,
+        }
+    }
+
+    /// Return the target of this rule if it matches path, with any splat substituted
+    fn target_for(&self, path: &str) -> Option<String> {
+        match self.from.strip_suffix('*') {
+            Some(prefix) => {
+                let splat = path.strip_prefix(prefix)?;
+                Some(self.to.replace(SPLAT, splat))
+            }
+            None if trim_trailing_slash(path) == trim_trailing_slash(&self.from) => {
+                Some(self.to.clone())
+            }
+            None => None,
+        }
+    }
+}
+
+/// Split a rule target into the location, action and force flag, given as '<TO> [STATUS[!]]'
+fn parse_target(target: &str, default_status: StatusCode) -> (String, RuleAction, bool) {
+    let mut fields = target.split_whitespace();
+    let to = fields.next().unwrap_or("").to_string();
+    let status_field = fields.next().unwrap_or("");
+    let force = status_field.ends_with('!');
+    let status = status_field
+        .trim_end_matches('!')
+        .parse::<u16>()
+        .ok()
+        .and_then(|status| StatusCode::from_u16(status).ok())
+        .unwrap_or(default_status);
+
+    if status == StatusCode::OK {
+        (to, RuleAction::Rewrite, force)
+    } else {
+        (to, RuleAction::Redirect(status), force)
+    }
+}
+
+fn trim_trailing_slash(path: &str) -> &str {
+    match path.strip_suffix('/') {
+        Some(trimmed) if !trimmed.is_empty() => trimmed,
+        _ => path,
+    }
+}
+
+/// Look up resource_path in tree, applying the redirect and rewrite rules of the site
+///
+/// Forced rules (those with a STATUS ending in '!') are checked first and apply even if
+/// resource_path is a published file. Other redirects and rewrites, pretty URLs and the SPA
+/// fallback only apply when resource_path is not found, so they never hide a published file.
+///
+/// If the file was published with a compressed copy in one of encodings (e.g. '/app.js.br'
+/// alongside '/app.js') the copy is returned instead. The copies are listed in the SiteConfig,
+/// so finding one takes a single lookup.
+pub fn lookup_with_rules(
+    tree: &Tree,
+    site_config: &SiteConfig,
+    resource_path: &String,
+    encodings: &[ContentEncoding],
+) -> Result<SiteLookup, StatusCode> {
+    if let Some((location, status)) = site_config.redirect_for(resource_path, true) {
+        println!("DEBUG redirecting {resource_path} to {location} ({status}, forced)");
+        return Ok(SiteLookup::Redirect(location, status));
+    }
+    for path in site_config.forced_rewrite_paths(resource_path) {
+        if let Ok((datamap_chunk, data_address, content_type)) = tree.lookup_file(&path, true) {
+            println!("DEBUG rewriting {resource_path} to {path} (forced)");
+            return Ok(file_lookup(
+                tree,
+                site_config,
+                datamap_chunk,
+                data_address,
+                content_type,
+                path,
+                encodings,
+            ));
+        }
+    }
+
+    let status = match tree.lookup_file(resource_path, true) {
+        Ok((datamap_chunk, data_address, content_type)) => {
+            return Ok(file_lookup(
+                tree,
+                site_config,
+                datamap_chunk,
+                data_address,
+                content_type,
+                resource_path.clone(),
+                encodings,
+            ))
+        }
+        Err(status) => status,
+    };
+    if status != StatusCode::NOT_FOUND {
+        return Err(status);
+    }
+
+    if let Some((location, status)) = site_config.redirect_for(resource_path, false) {
+        println!("DEBUG redirecting {resource_path} to {location} ({status})");
+        return Ok(SiteLookup::Redirect(location, status));
+    }
+    for path in site_config.fallback_paths(resource_path) {
+        if let Ok((datamap_chunk, data_address, content_type)) = tree.lookup_file(&path, true) {
+            println!("DEBUG rewriting {resource_path} to {path}");
+            return Ok(file_lookup(
+                tree,
+                site_config,
+                datamap_chunk,
+                data_address,
+                content_type,
+                path,
+                encodings,
+            ));
+        }
+    }
+    Err(status)
+}
+
+/// Return a SiteLookup::File for a file found at path, using a compressed copy if there is one
+fn file_lookup(
+    tree: &Tree,
+    site_config: &SiteConfig,
+    datamap_chunk: String,
+    data_address: String,
+    content_type: Option<String>,
+    path: String,
+    encodings: &[ContentEncoding],
+) -> SiteLookup {
+    match compressed_copy(tree, site_config, &path, encodings) {
+        Some((encoding, copy_datamap_chunk, copy_data_address)) => {
+            println!("DEBUG using {} copy of {path}", encoding.header_value());
+            SiteLookup::File(
+                copy_datamap_chunk,
+                copy_data_address,
+                content_type,
+                path,
+                Some(encoding),
+            )
+        }
+        None => SiteLookup::File(datamap_chunk, data_address, content_type, path, None),
+    }
+}
+
+/// Find the first of encodings in which the site lists a compressed copy of the file at path
+fn compressed_copy(
+    tree: &Tree,
+    site_config: &SiteConfig,
+    path: &str,
+    encodings: &[ContentEncoding],
+) -> Option<(ContentEncoding, String, String)> {
+    let (encoding, copy_path) = site_config.compressed.copy_for(path, encodings)?;
+    match tree.lookup_file(&copy_path, false) {
+        Ok((copy_datamap_chunk, copy_data_address, _)) => {
+            Some((encoding, copy_datamap_chunk, copy_data_address))
+        }
+        Err(_) => {
+            println!("DEBUG {COMPRESSED_COPIES_FILE} lists {copy_path} but it was not found");
+            None
+        }
+    }
+}
+
+/// The SiteConfig of each site, keyed by the addresses of its awe.toml, _redirects and _encodings
+///
+/// Published files never change, so once read a SiteConfig is kept for the life of the app.
+pub struct SiteConfigs {
+    configs: Mutex<HashMap<String, Arc<SiteConfig>>>,
+    no_config: Arc<SiteConfig>,
+}
+
+impl SiteConfigs {
+    pub fn new() -> SiteConfigs {
+        SiteConfigs {
+            configs: Mutex::new(HashMap::new()),
+            no_config: Arc::new(SiteConfig::default()),
+        }
+    }
+
+    /// Return the SiteConfig for the site in tree, or the default if it has none of
+    /// awe.toml, _redirects and _encodings
+    pub async fn for_tree(
+        &self,
+        client: Option<&DwebClient>,
+        content_cache: Option<&ContentCache>,
+        tree: &Tree,
+    ) -> Arc<SiteConfig> {
+        let mut files = Vec::new();
+        let mut key = String::from("");
+        for file in [
+            SITE_CONFIG_FILE,
+            SITE_REDIRECTS_FILE,
+            COMPRESSED_COPIES_FILE,
+        ] {
+            let address = match tree.lookup_file(&String::from(file), false) {
+                Ok((datamap_chunk, data_address, _)) => {
+                    let (datamap_chunk, data_address) =
+                        datamap_and_address_from_hex(datamap_chunk, data_address);
+                    ContentCache::key_for(&datamap_chunk, &data_address)
+                        .map(|file_key| (file_key, datamap_chunk, data_address))
+                }
+                Err(_) => None,
+            };
+            if let Some((file_key, _, _)) = &address {
+                key = format!("{key}{file}={file_key};");
+            }
+            files.push((file, address));
+        }
+        if key.is_empty() {
+            return self.no_config.clone();
+        }
+        if let Some(config) = self.configs.lock().unwrap().get(&key) {
+            return config.clone();
+        }
+
+        let mut texts = Vec::<Option<String>>::new();
+        for (file, address) in files {
+            let Some((_, datamap_chunk, data_address)) = address else {
+                texts.push(None);
+                continue;
+            };
+            match get_content_cached(client, content_cache, datamap_chunk, data_address).await {
+                Ok(content) => texts.push(Some(String::from_utf8_lossy(&content).to_string())),
+                Err(e) => {
+                    // Not remembered, so that it is tried again once the network is back
+                    println!("Failed to fetch {file}: {e}");
+                    return self.no_config.clone();
+                }
+            }
+        }
+
+        let config = match SiteConfig::from_files(
+            texts[0].as_deref(),
+            texts[1].as_deref(),
+            texts[2].as_deref(),
+        ) {
+            Ok(config) => Arc::new(config),
+            Err(e) => {
+                // A broken awe.toml shouldn't break the site, so carry on without it
+                println!("Ignoring {SITE_CONFIG_FILE}: {e}");
+                self.no_config.clone()
+            }
+        };
+
+        self.configs.lock().unwrap().insert(key, config.clone());
+        config
+    }
+}
@@ -0,0 +1,174 @@
+/*
+Copyright (c) 2024-2025 Mark Hughes
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+//! Streaming of large files, a window at a time
+//!
+//! A file on Autonomi is stored as self-encrypted chunks listed in its DataMap. Any
+//! range of bytes can be decrypted from just the chunks which hold it, so a file of any
+//! size can be read while only holding one window of it in memory.
+
+use std::io::Write;
+
+use bytes::Bytes;
+use color_eyre::eyre::{eyre, Result};
+use futures::future::try_join_all;
+use self_encryption::{DataMap, EncryptedChunk};
+use xor_name::XorName;
+
+use autonomi::chunk::{ChunkAddress, DataMapChunk};
+use autonomi::client::data::DataAddress;
+
+use dweb::client::DwebClient;
+
+/// Files larger than this are streamed rather than fetched whole
+pub const STREAMING_THRESHOLD: u64 = 32 * 1024 * 1024;
+
+/// The most bytes decrypted in one step, which bounds memory use when streaming
+pub const STREAM_WINDOW: u64 = 8 * 1024 * 1024;
+
+/// A file which is read a range at a time
+pub struct DataStream {
+    client: DwebClient,
+    data_map: DataMap,
+    size: u64,
+}
+
+impl DataStream {
+    /// Prepare to stream the file at datamap_chunk or data_address
+    ///
+    /// Only the DataMap is fetched, so this is cheap however large the file.
+    pub async fn open(
+        client: &DwebClient,
+        datamap_chunk: Option<DataMapChunk>,
+        data_address: Option<DataAddress>,
+    ) -> Result<DataStream> {
+        let data_map_bytes = match (datamap_chunk, data_address) {
+            (Some(datamap_chunk), _) => Bytes::from(hex::decode(datamap_chunk.to_hex())?),
+            (None, Some(data_address)) => fetch_chunk(client, *data_address.xorname()).await?,
+            (None, None) => return Err(eyre!("No DataMapChunk or DataAddress to stream")),
+        };
+
+        // The DataMap of a large file is itself stored as a file, so may need unwrapping
+        let mut data_map: DataMap = rmp_serde::from_slice(&data_map_bytes)?;
+        while data_map.is_child() {
+            let chunks = fetch_chunks(client, &data_map, 0..data_map.infos().len()).await?;
+            let data_map_bytes = self_encryption::decrypt(&data_map, &chunks)?;
+            data_map = rmp_serde::from_slice(&data_map_bytes)?;
+        }
+
+        let size = data_map
+            .infos()
+            .iter()
+            .map(|info| info.src_size as u64)
+            .sum();
+        Ok(DataStream {
+            client: client.clone(),
+            data_map,
+            size,
+        })
+    }
+
+    /// The size of the file in bytes
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Return up to length bytes starting at position
+    ///
+    /// Only the chunks which hold the range are fetched. Callers should keep length
+    /// to about STREAM_WINDOW so that memory use stays bounded.
+    pub async fn read_range(&self, position: u64, length: u64) -> Result<Bytes> {
+        if position >= self.size {
+            return Ok(Bytes::new());
+        }
+        let length = length.min(self.size - position) as usize;
+        let seek_info = self_encryption::seek_info(self.size as usize, position as usize, length);
+        let first_index = *seek_info.index_range.start();
+        let last_index = *seek_info.index_range.end();
+
+        let chunks =
+            fetch_chunks(&self.client, &self.data_map, first_index..last_index + 1).await?;
+        Ok(self_encryption::decrypt_range(
+            &self.data_map,
+            &chunks,
+            seek_info.relative_pos,
+            length,
+        )?)
+    }
+
+    /// Return the whole of a file no larger than STREAMING_THRESHOLD, read one
+    /// STREAM_WINDOW at a time. Larger files must be read a range at a time.
+    pub async fn read_all(&self) -> Result<Bytes> {
+        if self.size > STREAMING_THRESHOLD {
+            return Err(eyre!(
+                "File of {} bytes is too large to read whole, the limit is {STREAMING_THRESHOLD}",
+                self.size
+            ));
+        }
+        let mut content = Vec::with_capacity(self.size as usize);
+        self.write_to(&mut content).await?;
+        Ok(Bytes::from(content))
+    }
+
+    /// Write the whole file to writer, one STREAM_WINDOW at a time
+    ///
+    /// Returns the number of bytes written.
+    pub async fn write_to<W: Write>(&self, writer: &mut W) -> Result<u64> {
+        let mut position = 0;
+        while position < self.size {
+            let window = self.read_range(position, STREAM_WINDOW).await?;
+            if window.is_empty() {
+                return Err(eyre!(
+                    "Stream ended early at byte {position} of {}",
+                    self.size
+                ));
+            }
+            writer.write_all(&window)?;
+            position += window.len() as u64;
+        }
+        writer.flush()?;
+        Ok(position)
+    }
+}
+
+/// Fetch the encrypted chunks of data_map whose index is in indices, in order
+async fn fetch_chunks(
+    client: &DwebClient,
+    data_map: &DataMap,
+    indices: std::ops::Range<usize>,
+) -> Result<Vec<EncryptedChunk>> {
+    let infos = data_map.infos();
+    let fetches = indices.map(|index| {
+        let dst_hash = infos
+            .iter()
+            .find(|info| info.index == index)
+            .map(|info| info.dst_hash);
+        async move {
+            let Some(dst_hash) = dst_hash else {
+                return Err(eyre!("DataMap has no chunk {index}"));
+            };
+            let content = fetch_chunk(client, dst_hash).await?;
+            Ok(EncryptedChunk { content })
+        }
+    });
+    try_join_all(fetches).await
+}
+
+async fn fetch_chunk(client: &DwebClient, name: XorName) -> Result<Bytes> {
+    let chunk = client.client.chunk_get(&ChunkAddress::new(name)).await?;
+    Ok(chunk.value)
+}
@@ -0,0 +1,234 @@
+/*
+Copyright (c) 2024-2025 Mark Hughes
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+//! The timeline of versions of a website, and notice of new versions for the UI
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use color_eyre::eyre::{eyre, Result};
+use serde::Serialize;
+use tauri::{AppHandle, Emitter, Manager};
+use xor_name::XorName;
+
+use autonomi::files::archive_public::ArchiveAddress;
+
+use dweb::client::DwebClient;
+use dweb::files::directory::Tree;
+use dweb::history::{History, HistoryAddress};
+
+use crate::awe_cache::{ContentCache, HistoryCache};
+use crate::awe_client::SharedClient;
+use crate::awe_names::NameRegistry;
+use crate::awe_session::BrowsingSessions;
+use crate::awe_url::{AweScheme, AweUrl};
+
+/// Event sent to a window when a newer version of the site it shows is published
+pub const EVENT_NEWER_VERSION: &str = "newer-version";
+
+/// The shortest time between checks for newer versions
+const MIN_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// One version of a website
+#[derive(Clone, Debug, Serialize)]
+pub struct VersionSummary {
+    pub version: u32,
+    /// When published (the timestamp of its History entry, in seconds since the Unix epoch)
+    pub published: u64,
+    pub graph_entry_address: String,
+    pub archive_address: String,
+    pub file_count: usize,
+    /// Total size of the files in bytes
+    pub total_size: u64,
+}
+
+/// Payload of EVENT_NEWER_VERSION
+#[derive(Clone, Debug, Serialize)]
+pub struct NewerVersion {
+    pub history_address: String,
+    pub version_loaded: u32,
+    pub latest_version: u32,
+}
+
+/// The VersionSummary of each version seen, keyed by History and version
+///
+/// A published version never changes so each summary is made only once.
+pub struct VersionTimelines {
+    summaries: Mutex<HashMap<(String, u32), VersionSummary>>,
+}
+
+impl VersionTimelines {
+    pub fn new() -> VersionTimelines {
+        VersionTimelines {
+            summaries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Return a summary of every version of a History, oldest first
+    ///
+    /// With no client (when offline) only versions summarised before are returned.
+    pub async fn timeline(
+        &self,
+        client: Option<&DwebClient>,
+        history_cache: &HistoryCache,
+        history_address: HistoryAddress,
+    ) -> Result<Vec<VersionSummary>> {
+        let address_hex = history_address.to_hex();
+        let (latest_version, _) = history_cache
+            .resolve(client, history_address, None)
+            .await
+            .map_err(|status| eyre!("Failed to resolve History {address_hex}: {status}"))?;
+
+        let all_summarised = {
+            let summaries = self.summaries.lock().unwrap();
+            (1..=latest_version)
+                .all(|version| summaries.contains_key(&(address_hex.clone(), version)))
+        };
+        let history = match client {
+            Some(client) if !all_summarised => Some(
+                History::<Tree>::from_history_address(client.clone(), history_address, false, 0)
+                    .await?,
+            ),
+            _ => None,
+        };
+
+        let mut timeline = Vec::new();
+        for version in 1..=latest_version {
+            let key = (address_hex.clone(), version);
+            if let Some(summary) = self.summaries.lock().unwrap().get(&key) {
+                timeline.push(summary.clone());
+                continue;
+            }
+            let (Some(client), Some(history)) = (client, history.as_ref()) else {
+                continue;
+            };
+
+            // The content of each entry in the History is the address of the archive of a version
+            let graph_entry = history.get_graph_entry_for_version(version).await?;
+            let archive_address = ArchiveAddress::new(XorName(graph_entry.content));
+            let (_, tree) = history_cache
+                .resolve(Some(client), history_address, Some(version))
+                .await
+                .map_err(|status| eyre!("Failed to fetch version {version}: {status}"))?;
+            let published = history_cache
+                .published_time(history, &address_hex, version)
+                .await
+                .map_err(|status| eyre!("Failed to get time of version {version}: {status}"))?;
+
+            let summary = VersionSummary {
+                version,
+                published,
+                graph_entry_address: graph_entry.address().to_hex(),
+                archive_address: archive_address.to_hex(),
+                file_count: tree.archive.iter().count(),
+                total_size: tree
+                    .archive
+                    .iter()
+                    .map(|(_path, _datamap_chunk, metadata)| metadata.size)
+                    .sum(),
+            };
+            self.summaries.lock().unwrap().insert(key, summary.clone());
+            timeline.push(summary);
+        }
+        Ok(timeline)
+    }
+}
+
+/// Check the site shown in each window for newer versions for as long as the app runs
+///
+/// When a version newer than the one loaded is found, the session's version_max is
+/// updated and EVENT_NEWER_VERSION is sent to the window. Each new version is sent once.
+pub async fn watch_for_newer_versions(app: AppHandle) {
+    let interval = app
+        .state::<HistoryCache>()
+        .latest_ttl()
+        .max(MIN_CHECK_INTERVAL);
+
+    loop {
+        tokio::time::sleep(interval).await;
+        let Ok(client) = app.state::<SharedClient>().get().await else {
+            continue;
+        };
+
+        for (webview_label, site_address) in app.state::<BrowsingSessions>().site_addresses() {
+            let Ok(awe_url) = AweUrl::parse(&site_address) else {
+                continue;
+            };
+            let Some(history_address) = site_history_address(&app, &client, &awe_url).await else {
+                continue;
+            };
+            let Ok((latest_version, _)) = app
+                .state::<HistoryCache>()
+                .resolve(Some(&client), history_address, None)
+                .await
+            else {
+                continue;
+            };
+
+            let newer_version =
+                app.state::<BrowsingSessions>()
+                    .with_session(&webview_label, |session| {
+                        let version_loaded = session.version_loaded();
+                        if latest_version <= session.version_max()
+                            || latest_version <= version_loaded
+                        {
+                            return None;
+                        }
+                        session.set_version_max(latest_version);
+                        Some(NewerVersion {
+                            history_address: history_address.to_hex(),
+                            version_loaded,
+                            latest_version,
+                        })
+                    });
+
+            if let Some(newer_version) = newer_version {
+                println!(
+                    "DEBUG version {} of {} available in webview '{webview_label}'",
+                    newer_version.latest_version, newer_version.history_address
+                );
+                if let Err(e) =
+                    app.emit_to(webview_label.as_str(), EVENT_NEWER_VERSION, newer_version)
+                {
+                    println!("Failed to send {EVENT_NEWER_VERSION} event: {e}");
+                }
+            }
+        }
+    }
+}
+
+/// The address of the History of a site given by an awv:// or awe:// URL
+async fn site_history_address(
+    app: &AppHandle,
+    client: &DwebClient,
+    awe_url: &AweUrl,
+) -> Option<HistoryAddress> {
+    match awe_url.scheme() {
+        AweScheme::History => awe_url.history_address().ok(),
+        AweScheme::Name => {
+            let name_registry = app.state::<Option<NameRegistry>>();
+            let content_cache = app.state::<Option<ContentCache>>();
+            name_registry
+                .as_ref()?
+                .resolve(Some(client), content_cache.as_ref(), awe_url.host())
+                .await
+                .ok()
+        }
+        _ => None,
+    }
+}
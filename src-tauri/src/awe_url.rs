@@ -0,0 +1,643 @@
+/*
+Copyright (c) 2024-2025 Mark Hughes
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+//! Parsing and formatting of awe URLs (awv://, awm://, awf:// and awe://)
+
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+use color_eyre::eyre::{eyre, Result};
+use xor_name::XorName;
+
+use autonomi::client::data::DataAddress;
+
+use dweb::helpers::convert::{awe_str_to_data_address, str_to_pointer_address};
+use dweb::history::HistoryAddress;
+
+use crate::awe_protocols::{
+    AWE_PROTOCOL_DIRECTORY, AWE_PROTOCOL_FILE, AWE_PROTOCOL_HISTORY, AWE_PROTOCOL_NAME,
+};
+
+const SCHEME_END: &str = "://";
+const VERSION_SEPARATOR: char = '@';
+const PATH_SEPARATOR: char = '/';
+const QUERY_SEPARATOR: char = '?';
+const FRAGMENT_SEPARATOR: char = '#';
+const DATE_FORMAT: &str = "%Y-%m-%d";
+/// A UTC time without ':', which is not allowed after the host of a URL
+const TIME_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+
+/// The kind of address in the host of an awe URL
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum AweScheme {
+    /// awv://<HISTORY-ADDRESS> for versions of a website
+    History,
+    /// awm://<DIRECTORY-ADDRESS> for a directory of files
+    Directory,
+    /// awf://<FILE-ADDRESS> for a single file
+    File,
+    /// awe://<NAME> for a website given a name with name-add
+    Name,
+}
+
+impl AweScheme {
+    pub const ALL: [AweScheme; 4] = [
+        AweScheme::History,
+        AweScheme::Directory,
+        AweScheme::File,
+        AweScheme::Name,
+    ];
+
+    /// The start of a URL using this scheme, including '://'
+    pub fn prefix(&self) -> &'static str {
+        match self {
+            AweScheme::History => AWE_PROTOCOL_HISTORY,
+            AweScheme::Directory => AWE_PROTOCOL_DIRECTORY,
+            AweScheme::File => AWE_PROTOCOL_FILE,
+            AweScheme::Name => AWE_PROTOCOL_NAME,
+        }
+    }
+
+    /// The scheme name without '://' (e.g. "awv")
+    pub fn name(&self) -> &'static str {
+        self.prefix().trim_end_matches(SCHEME_END)
+    }
+
+    fn from_name(name: &str) -> Option<AweScheme> {
+        let name = name.to_ascii_lowercase();
+        AweScheme::ALL
+            .into_iter()
+            .find(|scheme| scheme.name() == name)
+    }
+
+    /// True if the host is an address in hex rather than a name
+    fn has_hex_host(&self) -> bool {
+        *self != AweScheme::Name
+    }
+
+    /// True if the URL can select a version of a History
+    fn has_versions(&self) -> bool {
+        matches!(self, AweScheme::History | AweScheme::Name)
+    }
+}
+
+/// A version of a website given after the host (e.g. awv://<HISTORY-ADDRESS>@3/)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum VersionPin {
+    /// A version number, starting at 1
+    Version(u32),
+    /// The version that was live at the end of this day (UTC)
+    Date(NaiveDate),
+    /// The version that was live at this time (UTC)
+    Time(DateTime<Utc>),
+}
+
+impl VersionPin {
+    /// Parse a version number, a date (YYYY-MM-DD) or a UTC time (YYYYMMDDTHHMMSSZ)
+    pub fn parse(pin: &str) -> Result<VersionPin> {
+        if let Ok(version) = pin.parse::<u32>() {
+            if version == 0 {
+                return Err(eyre!("Version must be greater than 0"));
+            }
+            return Ok(VersionPin::Version(version));
+        }
+
+        if let Ok(date) = NaiveDate::parse_from_str(pin, DATE_FORMAT) {
+            return Ok(VersionPin::Date(date));
+        }
+
+        match NaiveDateTime::parse_from_str(pin, TIME_FORMAT) {
+            Ok(time) => Ok(VersionPin::Time(time.and_utc())),
+            Err(_) => Err(eyre!(
+                "Expected a version number, a date (YYYY-MM-DD) or a time (YYYYMMDDTHHMMSSZ) after '{VERSION_SEPARATOR}' but found '{pin}'"
+            )),
+        }
+    }
+
+    /// The time (seconds since the Unix epoch) at which the pinned version was live,
+    /// or None if the pin is a version number
+    pub fn live_at(&self) -> Option<u64> {
+        let timestamp = match self {
+            VersionPin::Version(_) => return None,
+            VersionPin::Date(date) => date.and_hms_opt(23, 59, 59)?.and_utc().timestamp(),
+            VersionPin::Time(time) => time.timestamp(),
+        };
+        Some(timestamp.max(0) as u64)
+    }
+}
+
+impl fmt::Display for VersionPin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VersionPin::Version(version) => write!(f, "{version}"),
+            VersionPin::Date(date) => write!(f, "{}", date.format(DATE_FORMAT)),
+            VersionPin::Time(time) => write!(f, "{}", time.format(TIME_FORMAT)),
+        }
+    }
+}
+
+/// A parsed awe URL: <SCHEME>://<HOST>[@<VERSION>][/<PATH>][?<QUERY>][#<FRAGMENT>]
+///
+/// The scheme and host are lowercase and the path always begins with '/', so the
+/// path of 'awv://<HISTORY-ADDRESS>' is '/'. Other than that, formatting an AweUrl
+/// gives back the URL it was parsed from, and parsing the result of formatting gives
+/// back the same AweUrl.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AweUrl {
+    scheme: AweScheme,
+    host: String,
+    version: Option<VersionPin>,
+    path: String,
+    query: Option<String>,
+    fragment: Option<String>,
+}
+
+impl AweUrl {
+    /// A URL for the root of host
+    pub fn new(scheme: AweScheme, host: &str) -> AweUrl {
+        AweUrl {
+            scheme,
+            host: host.to_ascii_lowercase(),
+            version: None,
+            path: String::from("/"),
+            query: None,
+            fragment: None,
+        }
+    }
+
+    /// Parse a URL, which must begin with one of the awe schemes
+    pub fn parse(url: &str) -> Result<AweUrl> {
+        let url = url.trim();
+        let Some((scheme_name, remainder)) = url.split_once(SCHEME_END) else {
+            return Err(eyre!(
+                "Missing scheme in '{url}', expected a URL beginning with awv://, awm://, awf:// or awe://"
+            ));
+        };
+        let Some(scheme) = AweScheme::from_name(scheme_name) else {
+            return Err(eyre!(
+                "Unknown scheme '{scheme_name}{SCHEME_END}' in '{url}'"
+            ));
+        };
+
+        let (remainder, fragment) = match remainder.split_once(FRAGMENT_SEPARATOR) {
+            Some((remainder, fragment)) => (remainder, Some(fragment.to_string())),
+            None => (remainder, None),
+        };
+        let (remainder, query) = match remainder.split_once(QUERY_SEPARATOR) {
+            Some((remainder, query)) => (remainder, Some(query.to_string())),
+            None => (remainder, None),
+        };
+        let (authority, path) = match remainder.find(PATH_SEPARATOR) {
+            Some(position) => remainder.split_at(position),
+            None => (remainder, "/"),
+        };
+
+        let (host, version) = match authority.split_once(VERSION_SEPARATOR) {
+            Some((host, pin)) => {
+                if !scheme.has_versions() {
+                    return Err(eyre!(
+                        "A version can't be given for {} URLs: '{url}'",
+                        scheme.prefix()
+                    ));
+                }
+                (host, Some(VersionPin::parse(pin)?))
+            }
+            None => (authority, None),
+        };
+
+        let host = host.to_ascii_lowercase();
+        if host.is_empty() {
+            return Err(eyre!("Missing address or name in '{url}'"));
+        }
+        let host_is_valid = if scheme.has_hex_host() {
+            host.chars().all(|c| c.is_ascii_hexdigit())
+        } else {
+            host.chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+        };
+        if !host_is_valid {
+            return Err(eyre!("Invalid address or name '{host}' in '{url}'"));
+        }
+
+        Ok(AweUrl {
+            scheme,
+            host,
+            version,
+            path: path.to_string(),
+            query,
+            fragment,
+        })
+    }
+
+    /// Parse a URL, or if it has no scheme, a URL using default_scheme
+    /// (e.g. so that a bare address can be given on the command line)
+    pub fn parse_with_default_scheme(url: &str, default_scheme: AweScheme) -> Result<AweUrl> {
+        let url = url.trim();
+        if url.contains(SCHEME_END) {
+            AweUrl::parse(url)
+        } else {
+            AweUrl::parse(&format!("{}{url}", default_scheme.prefix()))
+        }
+    }
+
+    /// Parse a URL given on the command line, where a bare address is taken as a History
+    pub fn from_cli(url: &str) -> Result<AweUrl> {
+        AweUrl::parse_with_default_scheme(url, AweScheme::History)
+    }
+
+    pub fn scheme(&self) -> AweScheme {
+        self.scheme
+    }
+
+    /// The address (in hex) or name in the URL
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    pub fn version(&self) -> Option<&VersionPin> {
+        self.version.as_ref()
+    }
+
+    /// The path as given in the URL, which is percent-encoded
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// The path with percent-encoding removed, for looking up a file
+    pub fn resource_path(&self) -> String {
+        percent_decode(&self.path)
+    }
+
+    pub fn query(&self) -> Option<&str> {
+        self.query.as_deref()
+    }
+
+    /// The decoded parameters of the query
+    pub fn query_params(&self) -> HashMap<String, String> {
+        match &self.query {
+            Some(query) => url::form_urlencoded::parse(query.as_bytes())
+                .into_owned()
+                .collect(),
+            None => HashMap::new(),
+        }
+    }
+
+    pub fn fragment(&self) -> Option<&str> {
+        self.fragment.as_deref()
+    }
+
+    /// The scheme, host and any version, to which an absolute path can be appended
+    pub fn site_root(&self) -> String {
+        match &self.version {
+            Some(version) => format!(
+                "{}{}{VERSION_SEPARATOR}{version}",
+                self.scheme.prefix(),
+                self.host
+            ),
+            None => format!("{}{}", self.scheme.prefix(), self.host),
+        }
+    }
+
+    /// A copy of this URL with a different scheme and host, keeping everything else
+    pub fn with_host(&self, scheme: AweScheme, host: &str) -> AweUrl {
+        AweUrl {
+            scheme,
+            host: host.to_ascii_lowercase(),
+            ..self.clone()
+        }
+    }
+
+    pub fn with_version(mut self, version: Option<VersionPin>) -> AweUrl {
+        self.version = version;
+        self
+    }
+
+    /// Replace the path, which is given percent-encoded
+    pub fn with_path(mut self, path: &str) -> AweUrl {
+        self.path = format!("/{}", path.trim_start_matches(PATH_SEPARATOR));
+        self
+    }
+
+    pub fn with_query(mut self, query: Option<&str>) -> AweUrl {
+        self.query = query.map(|query| query.to_string());
+        self
+    }
+
+    /// The HistoryAddress of an awv:// URL
+    pub fn history_address(&self) -> Result<HistoryAddress> {
+        if self.scheme != AweScheme::History {
+            return Err(eyre!(
+                "Expected an {AWE_PROTOCOL_HISTORY} URL but found '{self}'"
+            ));
+        }
+        str_to_pointer_address(&self.host)
+            .map_err(|e| eyre!("Invalid History address '{}': {e}", self.host))
+    }
+
+    /// The DataAddress of an awm:// or awf:// URL
+    pub fn data_address(&self) -> Result<DataAddress> {
+        if !matches!(self.scheme, AweScheme::Directory | AweScheme::File) {
+            return Err(eyre!(
+                "Expected an {AWE_PROTOCOL_DIRECTORY} or {AWE_PROTOCOL_FILE} URL but found '{self}'"
+            ));
+        }
+        awe_str_to_data_address(&self.host)
+            .map_err(|e| eyre!("Invalid data address '{}': {e}", self.host))
+    }
+
+    /// The XorName of an awm:// or awf:// URL, whose host is a 32 byte address
+    pub fn xor_name(&self) -> Result<XorName> {
+        if !matches!(self.scheme, AweScheme::Directory | AweScheme::File) {
+            return Err(eyre!(
+                "Expected an {AWE_PROTOCOL_DIRECTORY} or {AWE_PROTOCOL_FILE} URL but found '{self}'"
+            ));
+        }
+        let bytes = hex::decode(&self.host)
+            .map_err(|e| eyre!("XorName '{}' not valid due to {e:?}", self.host))?;
+        match bytes.try_into() {
+            Ok(xor_name_bytes) => Ok(XorName(xor_name_bytes)),
+            Err(e) => Err(eyre!("XorName '{}' not valid due to {e:?}", self.host)),
+        }
+    }
+}
+
+impl fmt::Display for AweUrl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", self.site_root(), self.path)?;
+        if let Some(query) = &self.query {
+            write!(f, "{QUERY_SEPARATOR}{query}")?;
+        }
+        if let Some(fragment) = &self.fragment {
+            write!(f, "{FRAGMENT_SEPARATOR}{fragment}")?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for AweUrl {
+    type Err = color_eyre::eyre::Report;
+
+    fn from_str(url: &str) -> Result<AweUrl> {
+        AweUrl::parse(url)
+    }
+}
+
+/// Decode %XX sequences in text, leaving any that are not valid unchanged
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).unwrap_or("");
+            if let Ok(byte) = u8::from_str_radix(hex, 16) {
+                decoded.push(byte);
+                i += 3;
+                continue;
+            }
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+/// Percent-encode each segment of a path for use in a URL, keeping the '/' separators
+pub fn percent_encode_path(path: &str) -> String {
+    let mut encoded = String::with_capacity(path.len());
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                encoded.push(byte as char)
+            }
+            byte => encoded.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HISTORY: &str = "a1b2c3d4e5f6a1b2c3d4e5f6a1b2c3d4e5f6a1b2c3d4e5f6a1b2c3d4e5f6a1b2";
+    const DIRECTORY: &str = "0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef";
+
+    #[test]
+    fn parses_each_scheme() {
+        for scheme in AweScheme::ALL {
+            let host = if scheme == AweScheme::Name {
+                "my-site"
+            } else {
+                DIRECTORY
+            };
+            let url = AweUrl::parse(&format!("{}{host}/index.html", scheme.prefix())).unwrap();
+            assert_eq!(url.scheme(), scheme);
+            assert_eq!(url.host(), host);
+            assert_eq!(url.path(), "/index.html");
+        }
+    }
+
+    #[test]
+    fn missing_path_is_root() {
+        let url = AweUrl::parse(&format!("awm://{DIRECTORY}")).unwrap();
+        assert_eq!(url.path(), "/");
+        assert_eq!(url.to_string(), format!("awm://{DIRECTORY}/"));
+        assert_eq!(url, AweUrl::parse(&format!("awm://{DIRECTORY}/")).unwrap());
+    }
+
+    #[test]
+    fn keeps_trailing_slash() {
+        let url = AweUrl::parse(&format!("awm://{DIRECTORY}/docs/")).unwrap();
+        assert_eq!(url.path(), "/docs/");
+        assert_eq!(url.to_string(), format!("awm://{DIRECTORY}/docs/"));
+    }
+
+    #[test]
+    fn parses_query_and_fragment() {
+        let url = AweUrl::parse(&format!("awv://{HISTORY}/page.html?v=3&list=1#top")).unwrap();
+        assert_eq!(url.path(), "/page.html");
+        assert_eq!(url.query(), Some("v=3&list=1"));
+        assert_eq!(url.fragment(), Some("top"));
+        let params = url.query_params();
+        assert_eq!(params.get("v").map(String::as_str), Some("3"));
+        assert_eq!(params.get("list").map(String::as_str), Some("1"));
+    }
+
+    #[test]
+    fn query_without_path() {
+        let url = AweUrl::parse(&format!("awf://{DIRECTORY}?type=text/plain")).unwrap();
+        assert_eq!(url.path(), "/");
+        assert_eq!(
+            url.query_params().get("type").map(String::as_str),
+            Some("text/plain")
+        );
+    }
+
+    #[test]
+    fn parses_version_number() {
+        let url = AweUrl::parse(&format!("awv://{HISTORY}@3/about/")).unwrap();
+        assert_eq!(url.host(), HISTORY);
+        assert_eq!(url.version(), Some(&VersionPin::Version(3)));
+        assert_eq!(url.path(), "/about/");
+        assert_eq!(url.site_root(), format!("awv://{HISTORY}@3"));
+    }
+
+    #[test]
+    fn parses_version_date_and_time() {
+        let url = AweUrl::parse(&format!("awv://{HISTORY}@2025-03-01/")).unwrap();
+        let date = NaiveDate::from_ymd_opt(2025, 3, 1).unwrap();
+        assert_eq!(url.version(), Some(&VersionPin::Date(date)));
+        assert_eq!(url.version().unwrap().live_at(), Some(1740873599));
+
+        let url = AweUrl::parse(&format!("awe://my-site@20250301T120000Z/")).unwrap();
+        assert_eq!(url.version().unwrap().live_at(), Some(1740830400));
+        assert_eq!(url.to_string(), "awe://my-site@20250301T120000Z/");
+        assert_eq!(VersionPin::Version(2).live_at(), None);
+    }
+
+    #[test]
+    fn rejects_bad_versions() {
+        assert!(AweUrl::parse(&format!("awv://{HISTORY}@0/")).is_err());
+        assert!(AweUrl::parse(&format!("awv://{HISTORY}@latest/")).is_err());
+        assert!(AweUrl::parse(&format!("awv://{HISTORY}@/")).is_err());
+        // A ':' can't follow the host, so times must be given without one
+        assert!(AweUrl::parse(&format!("awv://{HISTORY}@2025-03-01T12:00:00Z/")).is_err());
+        assert!(AweUrl::parse(&format!("awm://{DIRECTORY}@3/")).is_err());
+        assert!(AweUrl::parse(&format!("awf://{DIRECTORY}@3")).is_err());
+    }
+
+    #[test]
+    fn rejects_bad_schemes_and_hosts() {
+        assert!(AweUrl::parse(&format!("https://{DIRECTORY}/")).is_err());
+        assert!(AweUrl::parse(DIRECTORY).is_err());
+        assert!(AweUrl::parse("awv:///index.html").is_err());
+        assert!(AweUrl::parse("awv://not-hex/").is_err());
+        assert!(AweUrl::parse(&format!("awm://{DIRECTORY}:8080/")).is_err());
+        assert!(AweUrl::parse("awe://bad_name/").is_err());
+    }
+
+    #[test]
+    fn default_scheme_applies_only_without_scheme() {
+        let url = AweUrl::parse_with_default_scheme(HISTORY, AweScheme::History).unwrap();
+        assert_eq!(url.scheme(), AweScheme::History);
+        assert_eq!(url.to_string(), format!("awv://{HISTORY}/"));
+
+        let url = AweUrl::from_cli(&format!("awm://{DIRECTORY}/a.txt")).unwrap();
+        assert_eq!(url.scheme(), AweScheme::Directory);
+    }
+
+    #[test]
+    fn normalises_case() {
+        let url = AweUrl::parse(&format!("AWM://{}/Docs/", DIRECTORY.to_uppercase())).unwrap();
+        assert_eq!(url.scheme(), AweScheme::Directory);
+        assert_eq!(url.host(), DIRECTORY);
+        // Paths are case sensitive
+        assert_eq!(url.path(), "/Docs/");
+    }
+
+    #[test]
+    fn trims_whitespace() {
+        let url = AweUrl::parse(&format!("  awm://{DIRECTORY}/ \n")).unwrap();
+        assert_eq!(url.to_string(), format!("awm://{DIRECTORY}/"));
+    }
+
+    #[test]
+    fn decodes_resource_path() {
+        let url = AweUrl::parse(&format!("awm://{DIRECTORY}/my%20file%2Bv2.html")).unwrap();
+        assert_eq!(url.path(), "/my%20file%2Bv2.html");
+        assert_eq!(url.resource_path(), "/my file+v2.html");
+
+        let url = AweUrl::parse(&format!("awm://{DIRECTORY}/100%/%zz%4")).unwrap();
+        assert_eq!(url.resource_path(), "/100%/%zz%4");
+    }
+
+    #[test]
+    fn encodes_paths_which_decode_back() {
+        let path = "/my docs/a&b?#%/ü.html";
+        let encoded = percent_encode_path(path);
+        assert_eq!(encoded, "/my%20docs/a%26b%3F%23%25/%C3%BC.html");
+        let url = AweUrl::parse(&format!("awm://{DIRECTORY}{encoded}")).unwrap();
+        assert_eq!(url.resource_path(), path);
+    }
+
+    #[test]
+    fn formats_canonical_urls_unchanged() {
+        let urls = [
+            format!("awv://{HISTORY}/"),
+            format!("awv://{HISTORY}@12/blog/post.html"),
+            format!("awv://{HISTORY}@2025-03-01/"),
+            format!("awv://{HISTORY}/search?q=a%20b&v=2#results"),
+            format!("awm://{DIRECTORY}/docs/"),
+            format!("awm://{DIRECTORY}/docs/?list=1"),
+            format!("awf://{DIRECTORY}/?type=image/png"),
+            format!("awe://my-site@4/about#team"),
+        ];
+        for url in urls {
+            assert_eq!(AweUrl::parse(&url).unwrap().to_string(), url);
+        }
+    }
+
+    #[test]
+    fn round_trips_through_display() {
+        let time = NaiveDate::from_ymd_opt(2025, 3, 1)
+            .unwrap()
+            .and_hms_opt(11, 30, 0)
+            .unwrap()
+            .and_utc();
+        let urls = [
+            AweUrl::new(AweScheme::History, HISTORY),
+            AweUrl::new(AweScheme::History, HISTORY)
+                .with_version(Some(VersionPin::Time(time)))
+                .with_path("a/b c.html"),
+            AweUrl::new(AweScheme::Name, "My-Site")
+                .with_version(Some(VersionPin::Version(1)))
+                .with_query(Some("x=1")),
+            AweUrl::new(AweScheme::File, DIRECTORY).with_query(Some("type=text/html")),
+        ];
+        for url in urls {
+            assert_eq!(AweUrl::parse(&url.to_string()).unwrap(), url);
+            assert_eq!(url.to_string().parse::<AweUrl>().unwrap(), url);
+        }
+    }
+
+    #[test]
+    fn with_host_keeps_the_rest() {
+        let url = AweUrl::parse("awe://my-site@2/page.html?a=1#top").unwrap();
+        let awv_url = url.with_host(AweScheme::History, HISTORY);
+        assert_eq!(
+            awv_url.to_string(),
+            format!("awv://{HISTORY}@2/page.html?a=1#top")
+        );
+    }
+
+    #[test]
+    fn typed_addresses_need_the_right_scheme() {
+        let url = AweUrl::parse(&format!("awm://{DIRECTORY}/")).unwrap();
+        assert!(url.history_address().is_err());
+        assert_eq!(
+            url.xor_name().unwrap(),
+            XorName(hex::decode(DIRECTORY).unwrap().try_into().unwrap())
+        );
+
+        let url = AweUrl::parse(&format!("awv://{HISTORY}/")).unwrap();
+        assert!(url.data_address().is_err());
+        assert!(url.xor_name().is_err());
+    }
+}
@@ -32,6 +32,9 @@ use dweb::helpers::convert::*;
 use dweb::history::HistoryAddress;
 use dweb::token::ShowCost;
 
+use crate::awe_client::awe_str_to_history_address;
+use crate::awe_url::AweUrl;
+
 // TODO add example to each CLI subcommand
 
 ///! Command line options and usage
@@ -55,10 +58,17 @@ pub struct Opt {
     ///
     /// Use awv://<HISTORY-ADDRESS> to browse most recent version from the history. (Use --history-version to specify a version).
     ///
+    /// Use awv://<HISTORY-ADDRESS>@<VERSION>, awv://<HISTORY-ADDRESS>@<YYYY-MM-DD> or awv://<HISTORY-ADDRESS>@<YYYYMMDDTHHMMSSZ> to browse a version, or the version live on a date or at a time (UTC).
+    ///
     /// Use awm://<DIRECTORY-ADDRESS> to browse files or website from Tree
     ///
     /// Use awf://<FILE-ADDRESS> to load or fetch to a file rather than a website.
-    pub url: Option<String>,
+    ///
+    /// Use awe://<NAME> to browse a website using a name added with the name-add subcommand.
+    ///
+    /// A HISTORY-ADDRESS given without a scheme is browsed as awv://<HISTORY-ADDRESS>.
+    #[clap(value_name = "AWE-URL", value_parser = AweUrl::from_cli)]
+    pub url: Option<AweUrl>,
 
     /// Browse the specified version from the history
     #[clap(long, value_parser = greater_than_0)]
@@ -100,6 +110,43 @@ pub struct Opt {
     #[clap(long, verbatim_doc_comment)]
     pub network_id: Option<u8>,
 
+    /// Maximum size in megabytes of the on-disk cache of content fetched by the browser.
+    ///
+    /// Content on Autonomi never changes, so anything fetched can be kept and reused
+    /// instead of downloading it again. Set to 0 to disable the cache.
+    #[clap(long, value_name = "MEGABYTES", default_value = "1024")]
+    pub cache_size: u64,
+
+    /// How long in seconds the browser remembers which is the latest version of a website
+    /// before checking the network again. Specific versions never change so are always remembered.
+    #[clap(long, value_name = "SECONDS", default_value = "30")]
+    pub latest_version_ttl: u64,
+
+    /// A list of web names shared with others, used by awe://<NAME> URLs when a name
+    /// is not in your local names file. Either a file path or an awf:// URL.
+    #[clap(long, value_name = "PATH-OR-AWF-URL")]
+    pub shared_names: Option<String>,
+
+    /// Show a listing of the files in a directory which has no index.html, so that
+    /// awm:// and awv:// can be used to browse files which are not a website.
+    ///
+    /// Without this, a listing is shown only for URLs which include '?list=1'.
+    #[clap(long)]
+    pub directory_listing: bool,
+
+    /// Browse without connecting to the network, serving only what is in the local
+    /// content and history caches.
+    ///
+    /// The browser also does this automatically while it is unable to connect.
+    #[clap(long)]
+    pub offline: bool,
+
+    /// Output format of subcommands, either "text" or "json".
+    ///
+    /// With "json" a subcommand prints a single JSON document, including when it fails.
+    #[clap(long, global = true, value_name = "FORMAT", default_value = "text", value_parser = str_to_output_format)]
+    pub output: OutputFormat,
+
     /// Enable Autonomi network logging (to the terminal)
     #[clap(long, name = "client-logs", short = 'l', default_value = "false")]
     pub client_logs: bool,
@@ -141,6 +188,25 @@ fn greater_than_0(s: &str) -> Result<u64, String> {
     }
 }
 
+/// How subcommands print their results
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    Text,
+    Json,
+}
+
+fn str_to_output_format(s: &str) -> Result<OutputFormat> {
+    match s {
+        "text" => Ok(OutputFormat::Text),
+        "json" => Ok(OutputFormat::Json),
+        _ => Err(eyre!("output format must be 'text' or 'json'")),
+    }
+}
+
+fn greater_than_0_u32(s: &str) -> Result<u32, String> {
+    greater_than_0(s).and_then(|value| u32::try_from(value).map_err(|e| e.to_string()))
+}
+
 #[derive(Subcommand, Debug)]
 pub enum Subcommands {
     // TODO add an example or two to each command section
@@ -170,6 +236,10 @@ pub enum Subcommands {
         /// Disable the AWV check when publishing a new website to allow for init of a new Autonomi network (during beta)
         #[clap(long, name = "is-new-network", hide = true, default_value = "false")]
         is_new_network: bool,
+        /// Also store Brotli (.br) and gzip (.gz) copies of text files such as HTML, CSS and
+        /// JavaScript, which are served in place of the originals to browsers which accept them
+        #[clap(long, default_value = "false")]
+        compress: bool,
     },
 
     /// Update a previously uploaded directory while preserving old versions on Autonomi
@@ -187,30 +257,85 @@ pub enum Subcommands {
         /// Defaults to use the name of the website directory (FILES-ROOT)
         #[clap(long, short = 'n')]
         name: Option<String>,
+        /// Also store Brotli (.br) and gzip (.gz) copies of text files such as HTML, CSS and
+        /// JavaScript, which are served in place of the originals to browsers which accept them
+        #[clap(long, default_value = "false")]
+        compress: bool,
+    },
+
+    /// Give a website a name for use in awe://<NAME> URLs
+    ///
+    /// Names are stored in a local file which you can also edit by hand (see name-list).
+    #[allow(non_camel_case_types)]
+    Name_add {
+        /// The name, using lowercase letters, digits and '-'
+        #[clap(value_name = "NAME")]
+        name: String,
+
+        /// The address of a website History. Can be prefixed with awv://
+        #[clap(name = "HISTORY-ADDRESS", value_parser = awe_str_to_history_address)]
+        history_address: HistoryAddress,
     },
 
-    /// Download a file or directory. TODO: not yet implemented
-    #[clap(hide = true)] // TODO hide until implemented
+    /// Remove a name added with name-add
+    #[allow(non_camel_case_types)]
+    Name_remove {
+        /// The name to remove
+        #[clap(value_name = "NAME")]
+        name: String,
+    },
+
+    /// List the names available for awe://<NAME> URLs
+    #[allow(non_camel_case_types)]
+    Name_list {},
+
+    /// Show what changed between two versions of a website
+    ///
+    /// Lists each path which was added, removed, modified or unchanged with the change in size.
+    Diff {
+        /// The address of a website History. Can be prefixed with awv://
+        #[clap(name = "HISTORY-ADDRESS", value_parser = awe_str_to_history_address)]
+        history_address: HistoryAddress,
+
+        /// The version to compare from
+        #[clap(long, value_name = "VERSION", value_parser = greater_than_0_u32)]
+        from: u32,
+
+        /// The version to compare to
+        #[clap(long, value_name = "VERSION", value_parser = greater_than_0_u32)]
+        to: u32,
+
+        /// Also print a unified diff of each modified text file
+        #[clap(long = "text", short = 't', default_value = "false")]
+        text_diffs: bool,
+
+        /// Omit unchanged paths from the table
+        #[clap(long = "changes-only", short = 'c', default_value = "false")]
+        changes_only: bool,
+    },
+
+    /// Download a file, a directory or versions of a website
+    ///
+    /// Existing files are never overwritten, so DOWNLOAD-PATH must not exist.
     Download {
         /// An awe compatible URL. Must be an xor address prefixed with 'awf://', 'awm://' or 'awv://' respectively
         /// to reference a FILE-ADDRESS, a DIRECTORY-ADDRESS or a HISTORY-ADDRESS with entries of Tree.
         ///
-        /// For a history, you must provide the RANGE of entries to be processed.
+        /// For a history, you must provide the RANGE of entries to be processed, or a version
+        /// in the URL such as 'awv://<HISTORY-ADDRESS>@3'.
         ///
         /// For a directory you may specify the path of a specific file or directory to be downloaded
         /// by including this at the end of the ARCHIVE-ADDRESS. This defaults to the directory root ('/').
         ///
-        /// If you do not specify a DOWNLOAD-PATH the content downloaded will be printed
-        /// on the terminal (via stdout).
-        // TODO implement a parser so I can validate here any combo of protocols (but keep as String here)
-        #[clap(value_name = "AWE-URL")]
-        awe_url: String,
+        /// If you do not specify a DOWNLOAD-PATH the content of a single file will be printed
+        /// on the terminal (via stdout). Anything more needs a DOWNLOAD-PATH.
+        #[clap(value_name = "AWE-URL", value_parser = AweUrl::parse)]
+        awe_url: AweUrl,
 
         /// A file or directory path where downloaded data is to be stored. This must not exist.
         /// If downloading more than a single file, DOWNLOAD-PATH must end with a file separator, and
         /// a directory will be created to hold the downloaded files and any subdirectories.
         #[clap(value_name = "DOWNLOAD-PATH")]
-        /// TODO: PathBuf?
         filesystem_path: Option<String>,
 
         /// If AWE-URL is a HISTORY-ADDRESS (i.e. starts with 'awv://') you must specify the entry or
@@ -1,3 +1,6 @@
 pub mod awe_subcommands;
+pub mod cmd_diff;
+pub mod cmd_download;
 pub mod cmd_inspect;
+pub mod cmd_names;
 pub mod helpers;
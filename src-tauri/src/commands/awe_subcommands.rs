@@ -15,19 +15,73 @@
  along with this program. If not, see <https://www.gnu.org/licenses/>.
 */
 
-use color_eyre::{Report, Result};
+use std::path::PathBuf;
 
-use autonomi::AttoTokens;
+use color_eyre::{
+    eyre::{eyre, WrapErr},
+    Result,
+};
+use serde::Serialize;
+use tempfile::TempDir;
+
+use autonomi::files::archive_public::ArchiveAddress;
+use autonomi::SecretKey;
+use evmlib::common::U256;
+use xor_name::XorName;
 
 use dweb::client::{ApiControl, DwebClient};
-use dweb::history::HistoryAddress;
+use dweb::files::directory::Tree;
+use dweb::history::{History, HistoryAddress};
 use dweb::storage::{publish_or_update_files, report_content_published_or_updated};
 use dweb::token::{show_spend_return_value, Spends};
 
-use crate::cli_options::{Opt, Subcommands};
+use crate::awe_compress::{stage_with_compressed_copies, CompressionSummary};
+use crate::awe_errors::CliError;
+use crate::awe_protocols::AWE_PROTOCOL_HISTORY;
+use crate::cli_options::{Opt, OutputFormat, Subcommands};
+use crate::commands::helpers::{files_of, print_error, print_json, FileInfo};
+
+#[derive(Debug, Serialize)]
+struct EstimateOutput {
+    files_root: PathBuf,
+    /// The estimated cost in atto tokens
+    cost: String,
+}
+
+#[derive(Debug, Serialize)]
+struct PublishOutput {
+    name: String,
+    history_address: String,
+    awe_url: String,
+    version: u32,
+    /// The cost of storage in atto tokens
+    cost: String,
+    /// The change in wallet balances, in atto tokens and in wei of gas
+    spent_tokens: String,
+    spent_gas: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    compression: Option<CompressionSummary>,
+    /// The files of the published version, as read back from the network
+    files: Vec<FileInfo>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    files_error: Option<String>,
+}
 
 // Returns true if command complete, false to start the browser
+//
+// With '--output json' an error is also printed as JSON, so each command prints a single
+// JSON document whether or not it succeeds. The process exit code for an error is
+// given by CliError::from_report().
 pub async fn cli_commands(opt: Opt) -> Result<bool> {
+    let output = opt.output;
+    let result = run_subcommand(opt).await;
+    if let Err(e) = &result {
+        print_error(output, e);
+    }
+    result
+}
+
+async fn run_subcommand(opt: Opt) -> Result<bool> {
     let api_control = ApiControl {
         tries: opt.retry_api,
         upload_file_by_file: opt.upload_file_by_file,
@@ -35,94 +89,65 @@ pub async fn cli_commands(opt: Opt) -> Result<bool> {
         max_fee_per_gas: opt.max_fee_per_gas,
         ..Default::default()
     };
+    let output = opt.output;
+    // Details of the connection would get in the way of a JSON document
+    let announce = output == OutputFormat::Text;
 
     match opt.cmd {
         Some(Subcommands::Estimate { files_root }) => {
             let (client, _is_local_network) =
-                connect_and_announce(opt.local, opt.alpha, api_control, true).await;
-            match client.client.file_cost(&files_root).await {
-                Ok(tokens) => println!("Cost estimate: {tokens}"),
-                Err(e) => println!("Unable to estimate cost: {e}"),
+                connect_and_announce(opt.local, opt.alpha, api_control, announce).await?;
+            let tokens = client
+                .client
+                .file_cost(&files_root)
+                .await
+                .wrap_err("Unable to estimate cost")?;
+            match output {
+                OutputFormat::Text => println!("Cost estimate: {tokens}"),
+                OutputFormat::Json => print_json(&EstimateOutput {
+                    files_root,
+                    cost: tokens.as_atto().to_string(),
+                })?,
             }
         }
         Some(Subcommands::Publish_new {
             files_root,
             name,
             is_new_network: _,
+            compress,
         }) => {
             let app_secret_key = dweb::helpers::get_app_secret_key()?;
-            let (client, _) = connect_and_announce(opt.local, opt.alpha, api_control, true).await;
-            let spends = Spends::new(&client, Some(&"Publish new cost: ")).await?;
-
-            let (cost, name, history_address, version) = match publish_or_update_files(
+            let (client, _) =
+                connect_and_announce(opt.local, opt.alpha, api_control, announce).await?;
+            publish(
                 &client,
-                &files_root,
                 app_secret_key,
+                files_root,
                 name,
-                None,
+                compress,
                 true,
+                output,
             )
-            .await
-            {
-                Ok(result) => {
-                    show_spend_return_value::<(AttoTokens, String, HistoryAddress, u32)>(
-                        &spends, result,
-                    )
-                    .await
-                }
-                Err(e) => {
-                    println!("Failed to publish files: {e}");
-                    return show_spend_return_value::<Result<bool, Report>>(&spends, Err(e)).await;
-                }
-            };
-
-            report_content_published_or_updated(
-                &history_address,
-                &name,
-                version,
-                cost,
-                &files_root,
-                true,
-                true,
-                true,
-            );
+            .await?;
         }
-        Some(Subcommands::Publish_update { files_root, name }) => {
+        Some(Subcommands::Publish_update {
+            files_root,
+            name,
+            compress,
+        }) => {
             let app_secret_key = dweb::helpers::get_app_secret_key()?;
-            let (client, _) = connect_and_announce(opt.local, opt.alpha, api_control, true).await;
-            let spends = Spends::new(&client, Some(&"Publish new cost: ")).await?;
-            let (cost, name, history_address, version) = match publish_or_update_files(
+            let (client, _) =
+                connect_and_announce(opt.local, opt.alpha, api_control, announce).await?;
+            publish(
                 &client,
-                &files_root,
                 app_secret_key,
+                files_root,
                 name,
-                None,
+                compress,
                 false,
+                output,
             )
-            .await
-            {
-                Ok(result) => {
-                    show_spend_return_value::<(AttoTokens, String, HistoryAddress, u32)>(
-                        &spends, result,
-                    )
-                    .await
-                }
-                Err(e) => {
-                    println!("Failed to publish files: {e}");
-                    return show_spend_return_value::<Result<bool, Report>>(&spends, Err(e)).await;
-                }
-            };
-
-            report_content_published_or_updated(
-                &history_address,
-                &name,
-                version,
-                cost,
-                &files_root,
-                true,
-                false,
-                true,
-            );
+            .await?;
         }
 
         Some(Subcommands::Inspect_history {
@@ -134,8 +159,9 @@ pub async fn cli_commands(opt: Opt) -> Result<bool> {
             graph_keys,
             files_args,
         }) => {
-            let (client, _) = connect_and_announce(opt.local, opt.alpha, api_control, true).await;
-            match crate::commands::cmd_inspect::handle_inspect_history(
+            let (client, _) =
+                connect_and_announce(opt.local, opt.alpha, api_control, announce).await?;
+            crate::commands::cmd_inspect::handle_inspect_history(
                 client,
                 history_address,
                 print_history_full,
@@ -144,15 +170,9 @@ pub async fn cli_commands(opt: Opt) -> Result<bool> {
                 graph_keys,
                 shorten_hex_strings,
                 files_args,
+                output,
             )
-            .await
-            {
-                Ok(()) => return Ok(true),
-                Err(e) => {
-                    println!("{e:?}");
-                    return Err(e);
-                }
-            }
+            .await?;
         }
 
         Some(Subcommands::Inspect_graphentry {
@@ -160,63 +180,103 @@ pub async fn cli_commands(opt: Opt) -> Result<bool> {
             print_full,
             shorten_hex_strings,
         }) => {
-            let (client, _) = connect_and_announce(opt.local, opt.alpha, api_control, true).await;
-            match crate::commands::cmd_inspect::handle_inspect_graphentry(
+            let (client, _) =
+                connect_and_announce(opt.local, opt.alpha, api_control, announce).await?;
+            crate::commands::cmd_inspect::handle_inspect_graphentry(
                 client,
                 graph_entry_address,
                 print_full,
                 shorten_hex_strings,
+                output,
             )
-            .await
-            {
-                Ok(()) => return Ok(true),
-                Err(e) => {
-                    println!("{e:?}");
-                    return Err(e);
-                }
-            }
+            .await?;
         }
 
         Some(Subcommands::Inspect_pointer { pointer_address }) => {
-            let (client, _) = connect_and_announce(opt.local, opt.alpha, api_control, true).await;
-            match crate::commands::cmd_inspect::handle_inspect_pointer(client, pointer_address)
-                .await
-            {
-                Ok(()) => return Ok(true),
-                Err(e) => {
-                    println!("{e:?}");
-                    return Err(e);
-                }
-            }
+            let (client, _) =
+                connect_and_announce(opt.local, opt.alpha, api_control, announce).await?;
+            crate::commands::cmd_inspect::handle_inspect_pointer(client, pointer_address, output)
+                .await?;
         }
 
         Some(Subcommands::Inspect_files {
             archive_address,
             files_args,
         }) => {
-            let (client, _) = connect_and_announce(opt.local, opt.alpha, api_control, true).await;
-            match crate::commands::cmd_inspect::handle_inspect_files(
+            let (client, _) =
+                connect_and_announce(opt.local, opt.alpha, api_control, announce).await?;
+            crate::commands::cmd_inspect::handle_inspect_files(
                 client,
                 archive_address,
                 files_args,
+                output,
             )
-            .await
-            {
-                Ok(_) => return Ok(true),
-                Err(e) => {
-                    println!("{e:?}");
-                    return Err(e);
-                }
-            }
+            .await?;
+        }
+
+        Some(Subcommands::Name_add {
+            name,
+            history_address,
+        }) => {
+            crate::commands::cmd_names::handle_name_add(name, history_address, output)?;
+        }
+
+        Some(Subcommands::Name_remove { name }) => {
+            crate::commands::cmd_names::handle_name_remove(name, output)?;
+        }
+
+        Some(Subcommands::Name_list {}) => {
+            // Only connect if there is a shared list to fetch
+            let client = if opt.shared_names.is_some() {
+                let (client, _) =
+                    connect_and_announce(opt.local, opt.alpha, api_control, false).await?;
+                Some(client)
+            } else {
+                None
+            };
+            crate::commands::cmd_names::handle_name_list(client, output).await?;
+        }
+
+        Some(Subcommands::Diff {
+            history_address,
+            from,
+            to,
+            text_diffs,
+            changes_only,
+        }) => {
+            let (client, _) =
+                connect_and_announce(opt.local, opt.alpha, api_control, announce).await?;
+            crate::commands::cmd_diff::handle_diff(
+                client,
+                history_address,
+                from,
+                to,
+                text_diffs,
+                changes_only,
+                output,
+            )
+            .await?;
         }
 
         Some(Subcommands::Download {
-            awe_url: _,
-            filesystem_path: _,
-            entries_range: _,
-            files_args: _,
+            awe_url,
+            filesystem_path,
+            entries_range,
+            files_args,
         }) => {
-            println!("TODO: implement subcommand 'download'");
+            // Nothing else is printed to stdout when that is where the content goes
+            let announce = announce && filesystem_path.is_some();
+            let (client, _) =
+                connect_and_announce(opt.local, opt.alpha, api_control, announce).await?;
+            crate::commands::cmd_download::handle_download(
+                client,
+                awe_url,
+                filesystem_path,
+                entries_range,
+                files_args,
+                output,
+            )
+            .await?;
         }
 
         // Default is not to return, but open the browser by continuing
@@ -228,16 +288,169 @@ pub async fn cli_commands(opt: Opt) -> Result<bool> {
     Ok(true)
 }
 
+/// Implement 'publish-new' (if is_new) and 'publish-update' subcommands
+async fn publish(
+    client: &DwebClient,
+    app_secret_key: SecretKey,
+    files_root: PathBuf,
+    name: Option<String>,
+    compress: bool,
+    is_new: bool,
+    output: OutputFormat,
+) -> Result<()> {
+    let (staged, publish_root, name) = stage_for_publish(&files_root, name, compress)?;
+    let compression = staged.as_ref().map(|(_, summary)| summary.clone());
+    if let (Some(compression), OutputFormat::Text) = (&compression, output) {
+        println!(
+            "Added compressed copies of {} files ({} bytes compressed to {})",
+            compression.files_compressed, compression.original_bytes, compression.compressed_bytes
+        );
+    }
+
+    // Spends are reported as part of the text output
+    let spends = match output {
+        OutputFormat::Text => {
+            let label = if is_new {
+                "Publish new cost: "
+            } else {
+                "Publish update cost: "
+            };
+            Some(Spends::new(client, Some(&label)).await?)
+        }
+        OutputFormat::Json => None,
+    };
+    let balances_before = Balances::of(client).await?;
+    let published =
+        publish_or_update_files(client, &publish_root, app_secret_key, name, None, is_new)
+            .await
+            .map_err(|e| e.wrap_err("Failed to publish files"));
+    let (cost, name, history_address, version) = match &spends {
+        Some(spends) => show_spend_return_value(spends, published).await?,
+        None => published?,
+    };
+    let balances_after = Balances::of(client).await?;
+
+    let (files, files_error) = match published_files(client, history_address, version).await {
+        Ok(files) => (files, None),
+        Err(e) => (Vec::new(), Some(e.to_string())),
+    };
+    let address_hex = history_address.to_hex();
+    let publish_output = PublishOutput {
+        name,
+        awe_url: format!("{AWE_PROTOCOL_HISTORY}{address_hex}"),
+        history_address: address_hex,
+        version,
+        cost: cost.as_atto().to_string(),
+        spent_tokens: balances_before
+            .tokens
+            .saturating_sub(balances_after.tokens)
+            .to_string(),
+        spent_gas: balances_before
+            .gas
+            .saturating_sub(balances_after.gas)
+            .to_string(),
+        compression,
+        files,
+        files_error,
+    };
+
+    match output {
+        OutputFormat::Json => print_json(&publish_output)?,
+        OutputFormat::Text => {
+            report_content_published_or_updated(
+                &history_address,
+                &publish_output.name,
+                version,
+                cost,
+                &files_root,
+                true,
+                is_new,
+                true,
+            );
+            if let Some(files_error) = &publish_output.files_error {
+                println!("Unable to list the files published: {files_error}");
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Return the files of a version of a History as stored on the network
+async fn published_files(
+    client: &DwebClient,
+    history_address: HistoryAddress,
+    version: u32,
+) -> Result<Vec<FileInfo>> {
+    let history =
+        History::<Tree>::from_history_address(client.clone(), history_address, false, 0).await?;
+    let graph_entry = history.get_graph_entry_for_version(version).await?;
+    let archive_address = ArchiveAddress::new(XorName(graph_entry.content));
+    let tree = Tree::from_archive_address(client, archive_address).await?;
+    Ok(files_of(&tree))
+}
+
+/// Wallet balances, used to report what publishing spent
+struct Balances {
+    /// In atto tokens
+    tokens: U256,
+    /// In wei
+    gas: U256,
+}
+
+impl Balances {
+    async fn of(client: &DwebClient) -> Result<Balances> {
+        Ok(Balances {
+            tokens: client.wallet.balance_of_tokens().await?,
+            gas: client.wallet.balance_of_gas_tokens().await?,
+        })
+    }
+}
+
+/// Return the directory to publish for files_root and the name to publish it under
+///
+/// With compress the files are published from a temporary copy which includes compressed
+/// copies, so the name must be given explicitly or it would default to that of the copy.
+/// The temporary copy is deleted when the returned TempDir is dropped.
+fn stage_for_publish(
+    files_root: &PathBuf,
+    name: Option<String>,
+    compress: bool,
+) -> Result<(
+    Option<(TempDir, CompressionSummary)>,
+    PathBuf,
+    Option<String>,
+)> {
+    if !compress {
+        return Ok((None, files_root.clone(), name));
+    }
+
+    let name = match name {
+        Some(name) => name,
+        None => files_root
+            .canonicalize()?
+            .file_name()
+            .map(|file_name| file_name.to_string_lossy().to_string())
+            .ok_or(eyre!(
+                "Unable to name website from FILES-ROOT, please use --name"
+            ))?,
+    };
+    let (staged, summary) = stage_with_compressed_copies(files_root)?;
+    let publish_root = staged.path().to_path_buf();
+    Ok((Some((staged, summary)), publish_root, Some(name)))
+}
+
 pub async fn connect_and_announce(
     local_network: bool,
     alpha_network: bool,
     api_control: ApiControl,
     announce: bool,
-) -> (DwebClient, bool) {
+) -> Result<(DwebClient, bool)> {
     let client =
         dweb::client::DwebClient::initialise_and_connect(local_network, alpha_network, api_control)
             .await
-            .expect("Failed to connect to Autonomi Network");
+            .map_err(|e| {
+                CliError::Connection(format!("Failed to connect to Autonomi Network: {e}"))
+            })?;
 
     if announce {
         if local_network {
@@ -249,5 +462,5 @@ pub async fn connect_and_announce(
         };
     };
 
-    (client, local_network)
+    Ok((client, local_network))
 }
@@ -0,0 +1,105 @@
+/*
+Copyright (c) 2024-2025 Mark Hughes
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+use color_eyre::Result;
+
+use dweb::client::DwebClient;
+use dweb::history::HistoryAddress;
+
+use crate::awe_cache::{ContentCache, HistoryCache};
+use crate::awe_diff::{diff_versions, Change, VersionDiff};
+use crate::cli_options::OutputFormat;
+use crate::commands::helpers::print_json;
+
+/// Implement 'diff' subcommand
+pub async fn handle_diff(
+    client: DwebClient,
+    history_address: HistoryAddress,
+    from_version: u32,
+    to_version: u32,
+    text_diffs: bool,
+    changes_only: bool,
+    output: OutputFormat,
+) -> Result<()> {
+    let history_cache = HistoryCache::new_default();
+    let content_cache = ContentCache::open_default();
+    let diff = diff_versions(
+        Some(&client),
+        &history_cache,
+        content_cache.as_ref(),
+        history_address,
+        from_version,
+        to_version,
+        text_diffs,
+    )
+    .await?;
+
+    match output {
+        OutputFormat::Json => print_json(&diff)?,
+        OutputFormat::Text => print_diff(&diff, changes_only),
+    }
+    Ok(())
+}
+
+fn print_diff(diff: &VersionDiff, changes_only: bool) {
+    println!(
+        "awv://{} v{} -> v{}",
+        diff.history_address, diff.from_version, diff.to_version
+    );
+    println!(
+        "{:<10} {:>12} {:>12} {:>12}  PATH",
+        "CHANGE", "FROM-SIZE", "TO-SIZE", "DELTA"
+    );
+    for path_change in &diff.changes {
+        if changes_only && path_change.change == Change::Unchanged {
+            continue;
+        }
+        println!(
+            "{:<10} {:>12} {:>12} {:>+12}  {}",
+            path_change.change.as_str(),
+            optional_size(path_change.from_size),
+            optional_size(path_change.to_size),
+            path_change.size_delta,
+            path_change.path
+        );
+    }
+    println!(
+        "{} added, {} removed, {} modified, {} unchanged, size change {:+} bytes",
+        diff.count(Change::Added),
+        diff.count(Change::Removed),
+        diff.count(Change::Modified),
+        diff.count(Change::Unchanged),
+        diff.size_delta()
+    );
+    let unknown = diff.count(Change::Unknown);
+    if unknown > 0 {
+        println!("{unknown} files could not be compared");
+    }
+
+    for path_change in &diff.changes {
+        if let Some(text_diff) = &path_change.text_diff {
+            println!();
+            print!("{text_diff}");
+        }
+    }
+}
+
+fn optional_size(size: Option<u64>) -> String {
+    match size {
+        Some(size) => size.to_string(),
+        None => String::from("-"),
+    }
+}
@@ -0,0 +1,448 @@
+/*
+Copyright (c) 2024-2025 Mark Hughes
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+use std::fs;
+use std::path::{Component, Path, PathBuf, MAIN_SEPARATOR};
+use std::time::{Duration, UNIX_EPOCH};
+
+use color_eyre::{eyre::eyre, Result};
+use serde::Serialize;
+use tempfile::NamedTempFile;
+
+use autonomi::chunk::DataMapChunk;
+use autonomi::client::data::DataAddress;
+
+use dweb::client::DwebClient;
+use dweb::files::directory::{datamap_and_address_from_hex, Tree};
+use dweb::history::HistoryAddress;
+
+use crate::awe_cache::HistoryCache;
+use crate::awe_errors::ProtocolError;
+use crate::awe_stream::DataStream;
+use crate::awe_url::{AweScheme, AweUrl, VersionPin};
+use crate::cli_options::{EntriesRange, FilesArgs, OutputFormat};
+use crate::commands::helpers::{format_time, print_json};
+
+#[derive(Debug, Serialize)]
+struct DownloadInfo {
+    awe_url: String,
+    files: Vec<DownloadedFile>,
+}
+
+/// A file written by the download subcommand
+#[derive(Debug, Serialize)]
+struct DownloadedFile {
+    /// The path in the archive, or the address for an awf:// URL
+    source: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    version: Option<u32>,
+    /// Where the file was saved, or None if written to stdout
+    file_path: Option<PathBuf>,
+    /// Bytes written
+    size: u64,
+}
+
+/// Implement 'download' subcommand
+///
+/// Without a filesystem_path the content is written to stdout, and any progress
+/// output or JSON document goes to stderr so that it doesn't mix with the content.
+pub async fn handle_download(
+    client: DwebClient,
+    awe_url: AweUrl,
+    filesystem_path: Option<String>,
+    entries_range: Option<EntriesRange>,
+    files_args: FilesArgs,
+    output: OutputFormat,
+) -> Result<()> {
+    if let Some(filesystem_path) = &filesystem_path {
+        if Path::new(filesystem_path).exists() {
+            return Err(eyre!(
+                "DOWNLOAD-PATH '{filesystem_path}' already exists, please choose a new path"
+            ));
+        }
+    }
+    let to_stdout = filesystem_path.is_none();
+    let destination = Destination::new(filesystem_path, files_args, output);
+
+    let files = match awe_url.scheme() {
+        AweScheme::File => {
+            let data_address = awe_url
+                .data_address()
+                .map_err(|e| ProtocolError::BadAddress(e.to_string()))?;
+            let file = destination
+                .download_file(&client, None, Some(data_address), None, None)
+                .await?;
+            vec![file]
+        }
+        AweScheme::Directory => {
+            let data_address = awe_url
+                .data_address()
+                .map_err(|e| ProtocolError::BadAddress(e.to_string()))?;
+            let tree = Tree::from_archive_address(&client, data_address).await?;
+            destination
+                .download_tree(&client, &tree, &awe_url.resource_path())
+                .await?
+        }
+        AweScheme::History => {
+            let history_address = awe_url
+                .history_address()
+                .map_err(|e| ProtocolError::BadAddress(e.to_string()))?;
+            download_versions(
+                &client,
+                history_address,
+                &awe_url,
+                entries_range,
+                destination,
+            )
+            .await?
+        }
+        AweScheme::Name => {
+            return Err(eyre!(
+                "Download needs an awf://, awm:// or awv:// URL, but found '{awe_url}'"
+            ))
+        }
+    };
+
+    if output == OutputFormat::Json {
+        let download_info = DownloadInfo {
+            awe_url: awe_url.to_string(),
+            files,
+        };
+        if to_stdout {
+            eprintln!("{}", serde_json::to_string_pretty(&download_info)?);
+        } else {
+            print_json(&download_info)?;
+        }
+    }
+    Ok(())
+}
+
+/// Download each version of a History in entries_range, or the version given in awe_url
+///
+/// When more than one version is downloaded each is put in a subdirectory named 'v<VERSION>'.
+async fn download_versions(
+    client: &DwebClient,
+    history_address: HistoryAddress,
+    awe_url: &AweUrl,
+    entries_range: Option<EntriesRange>,
+    destination: Destination,
+) -> Result<Vec<DownloadedFile>> {
+    let history_cache = HistoryCache::new_default();
+    let resource_path = awe_url.resource_path();
+
+    let versions = match (entries_range, awe_url.version()) {
+        (Some(entries_range), _) => {
+            let start = entries_range.start.unwrap_or(1);
+            let end = match entries_range.end {
+                Some(end) => end,
+                None => {
+                    let (latest_version, _) = history_cache
+                        .resolve(Some(client), history_address, None)
+                        .await
+                        .map_err(|status| {
+                            ProtocolError::from_status(
+                                status,
+                                format!("Failed to resolve History: {status}"),
+                            )
+                        })?;
+                    latest_version
+                }
+            };
+            if start == 0 {
+                return Err(eyre!(
+                    "Entry 0 of a History holds its type rather than a website, so RANGE must start at 1 or later"
+                ));
+            }
+            (start..=end).collect::<Vec<u32>>()
+        }
+        (None, Some(VersionPin::Version(version))) => vec![*version],
+        (None, Some(pin)) => {
+            let live_at = pin.live_at().unwrap_or_default();
+            let (version, _) = history_cache
+                .resolve_at(Some(client), history_address, live_at)
+                .await
+                .map_err(|status| {
+                    ProtocolError::from_status(
+                        status,
+                        format!("Failed to find the version live at {pin}: {status}"),
+                    )
+                })?;
+            vec![version]
+        }
+        (None, None) => {
+            return Err(eyre!(
+                "For an awv:// URL please specify the versions to download with --entries"
+            ))
+        }
+    };
+
+    let in_subdirectories = versions.len() > 1;
+    if in_subdirectories {
+        destination.require_directory()?;
+    }
+    let mut downloaded = Vec::new();
+    for version in versions {
+        let (_, tree) = history_cache
+            .resolve(Some(client), history_address, Some(version))
+            .await
+            .map_err(|status| {
+                ProtocolError::from_status(
+                    status,
+                    format!("Failed to fetch version {version}: {status}"),
+                )
+            })?;
+
+        let files = if in_subdirectories {
+            destination
+                .subdirectory(&format!("v{version}"))
+                .download_tree(client, &tree, &resource_path)
+                .await?
+        } else {
+            destination
+                .download_tree(client, &tree, &resource_path)
+                .await?
+        };
+        downloaded.extend(files.into_iter().map(|file| DownloadedFile {
+            version: Some(version),
+            ..file
+        }));
+    }
+    Ok(downloaded)
+}
+
+/// Where downloaded content is written: a file, a directory or stdout
+struct Destination {
+    path: Option<PathBuf>,
+    is_directory: bool,
+    print_paths: bool,
+    print_all_details: bool,
+    output: OutputFormat,
+}
+
+impl Destination {
+    fn new(
+        filesystem_path: Option<String>,
+        files_args: FilesArgs,
+        output: OutputFormat,
+    ) -> Destination {
+        let is_directory = filesystem_path
+            .as_ref()
+            .is_some_and(|path| path.ends_with('/') || path.ends_with(MAIN_SEPARATOR));
+        Destination {
+            path: filesystem_path.map(PathBuf::from),
+            is_directory,
+            print_paths: files_args.print_paths,
+            print_all_details: files_args.print_all_details,
+            output,
+        }
+    }
+
+    fn subdirectory(&self, name: &str) -> Destination {
+        Destination {
+            path: self.path.as_ref().map(|path| path.join(name)),
+            is_directory: true,
+            print_paths: self.print_paths,
+            print_all_details: self.print_all_details,
+            output: self.output,
+        }
+    }
+
+    fn require_directory(&self) -> Result<()> {
+        // Only a single file can be written to stdout
+        if self.path.is_none() || !self.is_directory {
+            return Err(eyre!(
+                "To download more than one file, give a DOWNLOAD-PATH ending with '{MAIN_SEPARATOR}'"
+            ));
+        }
+        Ok(())
+    }
+
+    /// Download the file or directory at resource_path in tree
+    async fn download_tree(
+        &self,
+        client: &DwebClient,
+        tree: &Tree,
+        resource_path: &str,
+    ) -> Result<Vec<DownloadedFile>> {
+        let prefix = resource_path.trim_matches('/');
+        let mut files = Vec::new();
+        for (path, _datamap_chunk, metadata) in tree.archive.iter() {
+            let path = path.to_string_lossy().replace('\\', "/");
+            let trimmed_path = path.trim_start_matches('/');
+            let relative_path = if prefix.is_empty() {
+                trimmed_path
+            } else if trimmed_path == prefix {
+                // A single file, named after itself
+                trimmed_path.rsplit('/').next().unwrap_or(trimmed_path)
+            } else if let Some(relative_path) = trimmed_path
+                .strip_prefix(prefix)
+                .and_then(|remainder| remainder.strip_prefix('/'))
+            {
+                relative_path
+            } else {
+                continue;
+            };
+            files.push((
+                path.clone(),
+                relative_path.to_string(),
+                metadata.size,
+                metadata.modified,
+            ));
+        }
+
+        if files.is_empty() {
+            return Err(eyre!("Nothing found at '{resource_path}'"));
+        }
+        let single_file =
+            files.len() == 1 && !prefix.is_empty() && files[0].0.trim_start_matches('/') == prefix;
+        if !single_file {
+            self.require_directory()?;
+        }
+
+        let mut downloaded = Vec::new();
+        for (path, relative_path, size, modified) in files {
+            let (datamap_chunk, data_address, _) =
+                tree.lookup_file(&path, false).map_err(|status| {
+                    ProtocolError::from_status(
+                        status,
+                        format!("Failed to look up '{path}': {status}"),
+                    )
+                })?;
+            let (datamap_chunk, data_address) =
+                datamap_and_address_from_hex(datamap_chunk, data_address);
+            let file_path = match &self.path {
+                Some(path) if self.is_directory => {
+                    Some(path.join(safe_relative_path(&relative_path)?))
+                }
+                Some(path) => Some(path.clone()),
+                None => None,
+            };
+            self.print_progress(&path, size, modified);
+            let file = self
+                .download_file(
+                    client,
+                    datamap_chunk,
+                    data_address,
+                    file_path,
+                    Some(modified),
+                )
+                .await?;
+            downloaded.push(DownloadedFile {
+                source: path,
+                ..file
+            });
+        }
+        Ok(downloaded)
+    }
+
+    /// Download a single file to file_path, or to this destination if file_path is None
+    ///
+    /// Files are streamed a window at a time so large files don't need to fit in memory.
+    async fn download_file(
+        &self,
+        client: &DwebClient,
+        datamap_chunk: Option<DataMapChunk>,
+        data_address: Option<DataAddress>,
+        file_path: Option<PathBuf>,
+        modified: Option<u64>,
+    ) -> Result<DownloadedFile> {
+        let source = data_address
+            .map(|data_address| data_address.to_hex())
+            .unwrap_or_default();
+        let stream = DataStream::open(client, datamap_chunk, data_address).await?;
+        let file_path = match (file_path, &self.path) {
+            (Some(file_path), _) => file_path,
+            (None, Some(path)) if self.is_directory => path.join(&source),
+            (None, Some(path)) => path.clone(),
+            (None, None) => {
+                let mut stdout = std::io::stdout().lock();
+                let written = stream.write_to(&mut stdout).await?;
+                return Ok(DownloadedFile {
+                    source,
+                    version: None,
+                    file_path: None,
+                    size: written,
+                });
+            }
+        };
+
+        let directory = match file_path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        fs::create_dir_all(directory)?;
+        // Written to a temporary file which is only renamed once complete, so a failed
+        // download leaves nothing behind to get in the way of trying again
+        let mut temp_file = NamedTempFile::new_in(directory)
+            .map_err(|e| eyre!("Failed to create a file in {directory:?}: {e}"))?;
+        let written = stream.write_to(temp_file.as_file_mut()).await?;
+        if let Some(modified) = modified {
+            temp_file
+                .as_file()
+                .set_modified(UNIX_EPOCH + Duration::from_secs(modified))?;
+        }
+        // Fails rather than overwrite an existing file
+        temp_file
+            .persist_noclobber(&file_path)
+            .map_err(|e| eyre!("Failed to create {file_path:?}: {}", e.error))?;
+        if self.print_paths || self.print_all_details {
+            self.message(&format!("  saved {written} bytes to {file_path:?}"));
+        }
+        Ok(DownloadedFile {
+            source,
+            version: None,
+            file_path: Some(file_path),
+            size: written,
+        })
+    }
+
+    fn print_progress(&self, path: &str, size: u64, modified: u64) {
+        if self.print_all_details {
+            let modified = format_time(modified);
+            self.message(&format!("{path} ({size} bytes, modified {modified})"));
+        } else if self.print_paths {
+            self.message(path);
+        }
+    }
+
+    /// Show a message without mixing it with content written to stdout, or
+    /// with the JSON document when that is the output
+    fn message(&self, message: &str) {
+        if self.output == OutputFormat::Json {
+            return;
+        }
+        if self.path.is_some() {
+            println!("{message}");
+        } else {
+            eprintln!("{message}");
+        }
+    }
+}
+
+/// Return relative_path if it stays within the download directory
+fn safe_relative_path(relative_path: &str) -> Result<PathBuf> {
+    let path = PathBuf::from(relative_path);
+    if path
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)))
+    {
+        Ok(path)
+    } else {
+        Err(eyre!(
+            "Refusing to download to unsafe path '{relative_path}'"
+        ))
+    }
+}
@@ -14,63 +14,291 @@ GNU Affero General Public License for more details.
 You should have received a copy of the GNU Affero General Public License
 along with this program. If not, see <https://www.gnu.org/licenses/>.
 */
-use color_eyre::Result;
+use color_eyre::{eyre::eyre, Result};
+use serde::Serialize;
+use xor_name::XorName;
 
 use autonomi::files::archive_public::ArchiveAddress;
-use autonomi::{GraphEntryAddress, Pointer, PointerAddress};
+use autonomi::{GraphEntry, GraphEntryAddress, Pointer, PointerAddress, PointerTarget};
 
 use dweb::client::DwebClient;
-use dweb::history::HistoryAddress;
+use dweb::files::directory::Tree;
+use dweb::history::{History, HistoryAddress};
 
-use crate::cli_options::{EntriesRange, FilesArgs};
+use crate::cli_options::{EntriesRange, FilesArgs, OutputFormat};
+use crate::commands::helpers::{files_of, format_hex, print_files, print_json, FileInfo};
+
+#[derive(Debug, Serialize)]
+struct HistoryInfo {
+    history_address: String,
+    num_entries: u32,
+    /// The content of entry 0, which gives the type of the History
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    history_type: Option<String>,
+    entries: Vec<HistoryEntryInfo>,
+}
+
+#[derive(Debug, Serialize)]
+struct HistoryEntryInfo {
+    index: u32,
+    #[serde(flatten)]
+    graph_entry: GraphEntryInfo,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    files: Option<Vec<FileInfo>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    files_error: Option<String>,
+}
+
+/// A graph entry with its parents and descendents given as addresses or public keys
+#[derive(Debug, Serialize)]
+struct GraphEntryInfo {
+    address: String,
+    owner: String,
+    content: String,
+    parents: Vec<String>,
+    descendents: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    signature: Option<String>,
+}
+
+impl GraphEntryInfo {
+    fn new(graph_entry: &GraphEntry, graph_keys: bool, with_signature: bool) -> GraphEntryInfo {
+        let key_or_address = |key: &blsttc::PublicKey| {
+            if graph_keys {
+                key.to_hex()
+            } else {
+                GraphEntryAddress::new(*key).to_hex()
+            }
+        };
+        GraphEntryInfo {
+            address: graph_entry.address().to_hex(),
+            owner: graph_entry.owner.to_hex(),
+            content: hex::encode(graph_entry.content),
+            parents: graph_entry.parents.iter().map(key_or_address).collect(),
+            descendents: graph_entry
+                .descendants
+                .iter()
+                .map(|(descendent, _)| key_or_address(descendent))
+                .collect(),
+            signature: with_signature.then(|| hex::encode(graph_entry.signature.to_bytes())),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct PointerInfo {
+    pointer_address: String,
+    target: String,
+    target_type: &'static str,
+    counter: u64,
+}
+
+#[derive(Debug, Serialize)]
+struct FilesInfo {
+    archive_address: String,
+    files: Vec<FileInfo>,
+}
 
 /// Implement 'inspect-history' subcommand
+///
+/// Entry 0 of a History holds its type, and entry N is the graph entry of version N,
+/// whose content is the address of the archive of that version.
 pub async fn handle_inspect_history(
-    _client: DwebClient,
-    _history_address: HistoryAddress,
-    _print_history_full: bool,
-    _entries_range: Option<EntriesRange>,
-    _include_files: bool,
-    _graph_keys: bool,
-    _shorten_hex_strings: bool,
-    _files_args: FilesArgs,
+    client: DwebClient,
+    history_address: HistoryAddress,
+    print_history_full: bool,
+    entries_range: Option<EntriesRange>,
+    include_files: bool,
+    graph_keys: bool,
+    shorten_hex_strings: bool,
+    files_args: FilesArgs,
+    output: OutputFormat,
 ) -> Result<()> {
-    println!("This awe subcommand is deprecated but you can 'cargo install dweb' and use dweb's subcommands instead");
+    let history =
+        History::<Tree>::from_history_address(client.clone(), history_address, false, 0).await?;
+    let num_entries = history.num_entries();
+
+    let history_type = if print_history_full {
+        let type_entry = history.get_graph_entry_for_version(0).await?;
+        Some(hex::encode(type_entry.content))
+    } else {
+        None
+    };
+
+    let mut entries = Vec::new();
+    if let (Some(entries_range), true) = (entries_range, num_entries > 0) {
+        let first = entries_range.start.unwrap_or(0);
+        let last = entries_range
+            .end
+            .unwrap_or(num_entries - 1)
+            .min(num_entries - 1);
+        if first > last {
+            return Err(eyre!(
+                "RANGE starts at entry {first} but the last entry is {}",
+                num_entries - 1
+            ));
+        }
+
+        for index in first..=last {
+            let graph_entry = history.get_graph_entry_for_version(index).await?;
+            let (files, files_error) = if include_files && index > 0 {
+                let archive_address = ArchiveAddress::new(XorName(graph_entry.content));
+                match Tree::from_archive_address(&client, archive_address).await {
+                    Ok(tree) => (Some(files_of(&tree)), None),
+                    Err(e) => (None, Some(e.to_string())),
+                }
+            } else {
+                (None, None)
+            };
+            entries.push(HistoryEntryInfo {
+                index,
+                graph_entry: GraphEntryInfo::new(&graph_entry, graph_keys, false),
+                files,
+                files_error,
+            });
+        }
+    }
+
+    let history_info = HistoryInfo {
+        history_address: history_address.to_hex(),
+        num_entries,
+        history_type,
+        entries,
+    };
+    match output {
+        OutputFormat::Json => print_json(&history_info)?,
+        OutputFormat::Text => print_history(&history_info, shorten_hex_strings, &files_args),
+    }
     Ok(())
 }
 
+fn print_history(history_info: &HistoryInfo, shorten_hex_strings: bool, files_args: &FilesArgs) {
+    println!("history     : {}", history_info.history_address);
+    println!("  entries   : {}", history_info.num_entries);
+    if let Some(history_type) = &history_info.history_type {
+        println!(
+            "  type      : {}",
+            format_hex(history_type, shorten_hex_strings)
+        );
+        println!(
+            "  versions  : {}",
+            history_info.num_entries.saturating_sub(1)
+        );
+    }
+
+    for entry in &history_info.entries {
+        println!("entry {}:", entry.index);
+        print_graph_entry(&entry.graph_entry, shorten_hex_strings);
+        if let Some(files) = &entry.files {
+            print_files("    ", files, files_args);
+        }
+        if let Some(files_error) = &entry.files_error {
+            println!("    failed to fetch files: {files_error}");
+        }
+    }
+}
+
+fn print_graph_entry(graph_entry: &GraphEntryInfo, shorten_hex_strings: bool) {
+    let hex = |hex: &String| format_hex(hex, shorten_hex_strings);
+    println!("  address   : {}", hex(&graph_entry.address));
+    println!("  owner     : {}", hex(&graph_entry.owner));
+    println!("  content   : {}", hex(&graph_entry.content));
+    for parent in &graph_entry.parents {
+        println!("  parent    : {}", hex(parent));
+    }
+    for descendent in &graph_entry.descendents {
+        println!("  descendent: {}", hex(descendent));
+    }
+    if let Some(signature) = &graph_entry.signature {
+        println!("  signature : {}", hex(signature));
+    }
+}
+
 /// Implement 'inspect-pointer' subcommand
 pub async fn handle_inspect_pointer(
-    _client: DwebClient,
-    _pointer_address: PointerAddress,
+    client: DwebClient,
+    pointer_address: PointerAddress,
+    output: OutputFormat,
 ) -> Result<()> {
-    println!("This awe subcommand is deprecated but you can 'cargo install dweb' and use dweb's subcommands instead");
+    let pointer = client.client.pointer_get(&pointer_address).await?;
+    match output {
+        OutputFormat::Json => print_json(&pointer_info(&pointer, &pointer_address))?,
+        OutputFormat::Text => print_pointer(&pointer, &pointer_address),
+    }
     Ok(())
 }
 
+fn pointer_info(pointer: &Pointer, pointer_address: &PointerAddress) -> PointerInfo {
+    let target_type = match pointer.target() {
+        PointerTarget::ChunkAddress(_) => "chunk",
+        PointerTarget::GraphEntryAddress(_) => "graph entry",
+        PointerTarget::PointerAddress(_) => "pointer",
+        PointerTarget::ScratchpadAddress(_) => "scratchpad",
+    };
+    PointerInfo {
+        pointer_address: pointer_address.to_hex(),
+        target: format!("{:x}", pointer.target().xorname()),
+        target_type,
+        counter: pointer.counter(),
+    }
+}
+
 fn print_pointer(pointer: &Pointer, pointer_address: &PointerAddress) {
-    println!("pointer     : {}", pointer_address.to_hex());
-    println!("  target    : {:x}", pointer.target().xorname());
-    println!("  counter   : {}", pointer.counter());
+    let pointer_info = pointer_info(pointer, pointer_address);
+    println!("pointer     : {}", pointer_info.pointer_address);
+    println!("  target    : {}", pointer_info.target);
+    println!("  type      : {}", pointer_info.target_type);
+    println!("  counter   : {}", pointer_info.counter);
 }
 
 /// Implement 'inspect-graphentry' subcommand
+///
+/// Parents and descendents are listed with full, and otherwise only counted.
 pub async fn handle_inspect_graphentry(
-    _client: DwebClient,
-    _graph_entry_address: GraphEntryAddress,
-    _full: bool,
-    _shorten_hex_strings: bool,
+    client: DwebClient,
+    graph_entry_address: GraphEntryAddress,
+    full: bool,
+    shorten_hex_strings: bool,
+    output: OutputFormat,
 ) -> Result<()> {
-    println!("This awe subcommand is deprecated but you can 'cargo install dweb' and use dweb's subcommands instead");
+    let graph_entry = client.client.graph_entry_get(&graph_entry_address).await?;
+    let graph_entry_info = GraphEntryInfo::new(&graph_entry, true, full);
+    if output == OutputFormat::Json {
+        return print_json(&graph_entry_info);
+    }
+
+    println!("graph entry :");
+    if full {
+        print_graph_entry(&graph_entry_info, shorten_hex_strings);
+    } else {
+        let hex = |hex: &String| format_hex(hex, shorten_hex_strings);
+        println!("  address   : {}", hex(&graph_entry_info.address));
+        println!("  owner     : {}", hex(&graph_entry_info.owner));
+        println!("  content   : {}", hex(&graph_entry_info.content));
+        println!("  parents   : {}", graph_entry_info.parents.len());
+        println!("  descendents: {}", graph_entry_info.descendents.len());
+    }
     Ok(())
 }
 
 /// Implement 'inspect-files' subcommand
 pub async fn handle_inspect_files(
-    _client: DwebClient,
-    _archive_address: ArchiveAddress,
-    _files_args: FilesArgs,
+    client: DwebClient,
+    archive_address: ArchiveAddress,
+    files_args: FilesArgs,
+    output: OutputFormat,
 ) -> Result<()> {
-    println!("This awe subcommand is deprecated but you can 'cargo install dweb' and use dweb's subcommands instead");
+    let tree = Tree::from_archive_address(&client, archive_address).await?;
+    let files_info = FilesInfo {
+        archive_address: archive_address.to_hex(),
+        files: files_of(&tree),
+    };
+    match output {
+        OutputFormat::Json => print_json(&files_info)?,
+        OutputFormat::Text => {
+            println!("directory   : {}", files_info.archive_address);
+            print_files("  ", &files_info.files, &files_args);
+        }
+    }
     Ok(())
 }
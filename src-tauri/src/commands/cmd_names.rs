@@ -0,0 +1,119 @@
+/*
+Copyright (c) 2024-2025 Mark Hughes
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+use std::collections::BTreeMap;
+
+use color_eyre::Result;
+use serde::Serialize;
+
+use dweb::client::DwebClient;
+use dweb::history::HistoryAddress;
+
+use crate::awe_names::NameRegistry;
+use crate::cli_options::OutputFormat;
+use crate::commands::helpers::print_json;
+
+#[derive(Debug, Serialize)]
+struct NameAdded {
+    name: String,
+    history_address: String,
+}
+
+#[derive(Debug, Serialize)]
+struct NameRemoved {
+    name: String,
+    /// False if the name was not in the local list
+    removed: bool,
+    local_path: String,
+}
+
+#[derive(Debug, Serialize)]
+struct NameList {
+    local_path: String,
+    local_names: BTreeMap<String, String>,
+    /// Only present when the shared list was fetched
+    #[serde(skip_serializing_if = "Option::is_none")]
+    shared_names: Option<BTreeMap<String, String>>,
+}
+
+/// Implement 'name-add' subcommand
+pub fn handle_name_add(
+    name: String,
+    history_address: HistoryAddress,
+    output: OutputFormat,
+) -> Result<()> {
+    let registry = NameRegistry::open_default()?;
+    registry.add(&name, history_address)?;
+    match output {
+        OutputFormat::Json => print_json(&NameAdded {
+            name,
+            history_address: history_address.to_hex(),
+        })?,
+        OutputFormat::Text => println!("awe://{name} -> {}", history_address.to_hex()),
+    }
+    Ok(())
+}
+
+/// Implement 'name-remove' subcommand
+pub fn handle_name_remove(name: String, output: OutputFormat) -> Result<()> {
+    let registry = NameRegistry::open_default()?;
+    let removed = registry.remove(&name)?;
+    match output {
+        OutputFormat::Json => print_json(&NameRemoved {
+            name,
+            removed,
+            local_path: registry.local_path().to_string_lossy().to_string(),
+        })?,
+        OutputFormat::Text if removed => println!("Removed awe://{name}"),
+        OutputFormat::Text => {
+            println!("Name '{name}' not found in {:?}", registry.local_path())
+        }
+    }
+    Ok(())
+}
+
+/// Implement 'name-list' subcommand
+///
+/// The shared list is only fetched when a client is provided
+pub async fn handle_name_list(client: Option<DwebClient>, output: OutputFormat) -> Result<()> {
+    let registry = NameRegistry::open_default()?;
+    let local_names = registry.local_names()?;
+    let shared_names = match client {
+        Some(client) => Some(registry.shared_names(Some(&client), None).await?),
+        None => None,
+    };
+
+    if output == OutputFormat::Json {
+        return print_json(&NameList {
+            local_path: registry.local_path().to_string_lossy().to_string(),
+            local_names,
+            shared_names,
+        });
+    }
+
+    println!("local names ({:?}):", registry.local_path());
+    for (name, address) in local_names {
+        println!("  awe://{name:<20} {address}");
+    }
+
+    if let Some(shared_names) = shared_names {
+        println!("shared names:");
+        for (name, address) in shared_names {
+            println!("  awe://{name:<20} {address}");
+        }
+    }
+    Ok(())
+}
@@ -14,3 +14,110 @@ GNU Affero General Public License for more details.
 You should have received a copy of the GNU Affero General Public License
 along with this program. If not, see <https://www.gnu.org/licenses/>.
 */
+use color_eyre::{Report, Result};
+use serde::Serialize;
+
+use dweb::files::directory::Tree;
+
+use crate::awe_errors::CliError;
+use crate::cli_options::{FilesArgs, OutputFormat};
+
+/// A file in an archive, as output by the inspect and download subcommands
+#[derive(Clone, Debug, Serialize)]
+pub struct FileInfo {
+    pub path: String,
+    /// Size in bytes
+    pub size: u64,
+    /// Modification time in seconds since the Unix epoch
+    pub modified: u64,
+}
+
+/// An error as output with '--output json'
+#[derive(Debug, Serialize)]
+struct ErrorOutput {
+    error: String,
+    /// One of the kinds of CliError
+    kind: &'static str,
+    exit_code: i32,
+    /// The errors which caused it, outermost first
+    causes: Vec<String>,
+}
+
+/// Return hex, or if shorten is true just its first six characters followed by '..'
+pub fn format_hex(hex: &str, shorten: bool) -> String {
+    if shorten && hex.len() > 6 {
+        format!("{}..", &hex[..6])
+    } else {
+        hex.to_string()
+    }
+}
+
+/// Return the files in tree, in the order stored
+pub fn files_of(tree: &Tree) -> Vec<FileInfo> {
+    tree.archive
+        .iter()
+        .map(|(path, _datamap_chunk, metadata)| FileInfo {
+            path: path.to_string_lossy().replace('\\', "/"),
+            size: metadata.size,
+            modified: metadata.modified,
+        })
+        .collect()
+}
+
+/// Print files as selected by files_args, indented by indent
+pub fn print_files(indent: &str, files: &[FileInfo], files_args: &FilesArgs) {
+    for file in files {
+        if files_args.print_all_details {
+            println!(
+                "{indent}{:<48} {} {:>12} bytes",
+                file.path,
+                format_time(file.modified),
+                file.size
+            );
+        } else if files_args.print_paths {
+            println!("{indent}{}", file.path);
+        }
+    }
+    let total_size: u64 = files.iter().map(|file| file.size).sum();
+    println!("{indent}{} files, {total_size} bytes", files.len());
+}
+
+/// Format seconds since the Unix epoch as a date and time
+pub fn format_time(seconds: u64) -> String {
+    chrono::DateTime::from_timestamp(seconds as i64, 0)
+        .map(|time| time.to_string())
+        .unwrap_or_default()
+}
+
+/// Print value as a JSON document
+pub fn print_json<T: Serialize>(value: &T) -> Result<()> {
+    println!("{}", serde_json::to_string_pretty(value)?);
+    Ok(())
+}
+
+/// Print the error which ended a subcommand in the chosen format
+///
+/// Errors go to stderr so that they are never mixed into output on stdout, such as a file
+/// being downloaded.
+pub fn print_error(output: OutputFormat, error: &Report) {
+    match output {
+        OutputFormat::Text => eprintln!("{error:?}"),
+        OutputFormat::Json => {
+            let cli_error = CliError::from_report(error);
+            let error_output = ErrorOutput {
+                error: error.to_string(),
+                kind: cli_error.kind(),
+                exit_code: cli_error.exit_code(),
+                causes: error
+                    .chain()
+                    .skip(1)
+                    .map(|cause| cause.to_string())
+                    .collect(),
+            };
+            match serde_json::to_string_pretty(&error_output) {
+                Ok(json) => eprintln!("{json}"),
+                Err(_) => eprintln!("{error:?}"),
+            }
+        }
+    }
+}
@@ -18,9 +18,21 @@
 // Prevents additional console window on Windows in release, DO NOT REMOVE!!
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
+mod awe_cache;
 mod awe_client;
+mod awe_compress;
 mod awe_const;
+mod awe_diff;
+mod awe_errors;
+mod awe_http;
+mod awe_listing;
+mod awe_names;
 mod awe_protocols;
+mod awe_session;
+mod awe_site;
+mod awe_stream;
+mod awe_timeline;
+mod awe_url;
 mod cli_options;
 mod commands;
 mod connect;
@@ -28,6 +40,7 @@ mod generated_rs;
 
 use ant_logging::{Level, LogBuilder};
 
+use crate::awe_errors::CliError;
 use crate::commands::awe_subcommands;
 
 // TODO fix messed up cursor keys in terminal after running CLI command.
@@ -90,15 +103,14 @@ pub fn run() {
         }
     };
 
-    let url = opt.url.clone();
+    let url = opt.url.as_ref().map(|url| url.to_string());
     let version = opt.history_version.clone();
 
-    if tauri::async_runtime::block_on(async move {
-        awe_subcommands::cli_commands(opt)
-            .await
-            .is_ok_and(|complete| !complete)
-    }) {
+    match tauri::async_runtime::block_on(awe_subcommands::cli_commands(opt)) {
         // No command complete, so register protocols and open the browser
-        crate::awe_protocols::register_protocols(url, version);
+        Ok(false) => crate::awe_protocols::register_protocols(url, version),
+        Ok(true) => {}
+        // The error has been printed, so just give a distinct exit code for scripts
+        Err(e) => std::process::exit(CliError::from_report(&e).exit_code()),
     };
 }